@@ -0,0 +1,61 @@
+//! Index of the stamps made by each account, oldest first.
+//!
+//! Every stamp of a file hash is indexed, including later stamps of an already stamped hash,
+//! so an account that stamps a hash twice lists it twice. A batch is indexed once, under the key
+//! of its Merkle root (see `get_batch_root`), not under each of its file hashes. Each account
+//! has its own vector, so listing a page costs the same however many stamps it made. The vector
+//! only holds the sequence numbers of the stamps, which are read from the `stamp_log` module.
+
+use crate::{ProofOfTimestamp, ACCOUNT_STAMPS_PREFIX};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountStamp {
+    /// Canonical file hash, or `merkle-root:<root>` for a batch.
+    pub file_hash: String,
+    pub timestamp: U64,
+    /// Sequence number of the stamp in the stamp log.
+    pub seq: U64,
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Returns up to `limit` stamps of `account_id`, oldest first, starting at index `from_index`.
+    pub fn get_stamps_by_account(&self, account_id: ValidAccountId, from_index: u64, limit: u64) -> Vec<AccountStamp> {
+        match self.account_stamps.get(account_id.as_ref()) {
+            Some(stamps) => (from_index..std::cmp::min(from_index.saturating_add(limit), stamps.len()))
+                .map(|index| self.account_stamp(stamps.get(index).unwrap()))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn count_stamps_by_account(&self, account_id: ValidAccountId) -> u64 {
+        self.account_stamps.get(account_id.as_ref()).map_or(0, |stamps| stamps.len())
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Appends the stamp `seq` to the index of `stamper`.
+    pub(crate) fn index_account_stamp(&mut self, stamper: &AccountId, seq: u64) {
+        let mut stamps = self.account_stamps.get(stamper).unwrap_or_else(|| {
+            let mut prefix = ACCOUNT_STAMPS_PREFIX.to_vec();
+            prefix.extend(env::sha256(stamper.as_bytes()));
+            Vector::new(prefix)
+        });
+        stamps.push(&seq);
+        self.account_stamps.insert(stamper, &stamps);
+    }
+
+    pub(crate) fn account_stamp(&self, seq: u64) -> AccountStamp {
+        let entry = self.log_entry(seq);
+        AccountStamp { file_hash: entry.file_hash, timestamp: entry.timestamp.into(), seq: seq.into() }
+    }
+}
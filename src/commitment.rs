@@ -0,0 +1,41 @@
+//! The commitment stored in `TimestampedFile::time_stamped_file_hash`.
+//!
+//! Version 1 commits to the keccak256 of
+//!
+//! ```text
+//! u32_be(len(DOMAIN)) || DOMAIN || version (1 byte) || u32_be(len(file_hash)) || file_hash || u64_be(timestamp)
+//! ```
+//!
+//! where `file_hash` is the UTF-8 canonical file hash (see the `file_hash` module) and
+//! `timestamp` is the block timestamp in nanoseconds. Every field has a fixed size or a length
+//! prefix, so two different (hash, timestamp) pairs never share a preimage.
+//!
+//! Version 0 is the scheme of the first release, `keccak256(file_hash || decimal timestamp)`,
+//! kept to recompute commitments of records stamped with it.
+//!
+//! Test vectors are published in `test-vectors/commitment.json`.
+
+pub const COMMITMENT_DOMAIN: &[u8] = b"near-proof-of-timestamp/commitment";
+
+/// Plain concatenation of the hash and the decimal timestamp, used by the first release.
+pub const LEGACY_COMMITMENT_VERSION: u8 = 0;
+pub const COMMITMENT_VERSION: u8 = 1;
+
+/// Bytes hashed into the commitment of `file_hash` stamped at `timestamp`, `None` for unknown
+/// scheme versions.
+pub fn commitment_preimage(version: u8, file_hash: &str, timestamp: u64) -> Option<Vec<u8>> {
+    match version {
+        LEGACY_COMMITMENT_VERSION => Some(format!("{}{}", file_hash, timestamp).into_bytes()),
+        COMMITMENT_VERSION => {
+            let mut preimage = Vec::with_capacity(4 + COMMITMENT_DOMAIN.len() + 1 + 4 + file_hash.len() + 8);
+            preimage.extend(&(COMMITMENT_DOMAIN.len() as u32).to_be_bytes());
+            preimage.extend(COMMITMENT_DOMAIN);
+            preimage.push(version);
+            preimage.extend(&(file_hash.len() as u32).to_be_bytes());
+            preimage.extend(file_hash.as_bytes());
+            preimage.extend(&timestamp.to_be_bytes());
+            Some(preimage)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,205 @@
+//! Structured logs following NEP-297, so that indexers can subscribe to stamps without parsing
+//! free text. Every event is logged as
+//!
+//! ```text
+//! EVENT_JSON:{"standard":"proof_of_timestamp","version":"1.0.0","event":"<name>","data":[...]}
+//! ```
+//!
+//! Certificate NFTs are announced with the `nft_mint` and `nft_transfer` events of the `nep171`
+//! standard instead, which wallets and marketplaces already index.
+
+use crate::roles::Role;
+use near_sdk::env;
+use near_sdk::json_types::U64;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+use near_sdk::AccountId;
+
+pub const EVENT_STANDARD: &str = "proof_of_timestamp";
+pub const EVENT_VERSION: &str = "1.0.0";
+pub const NFT_EVENT_STANDARD: &str = "nep171";
+pub const NFT_EVENT_VERSION: &str = "1.0.0";
+const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// First stamp of a file hash, which created its record.
+    StampCreated(Vec<StampData>),
+    /// Later stamp of an already stamped file hash.
+    StampObserved(Vec<StampData>),
+    BatchRootStamped(Vec<BatchRootData>),
+    RoleGranted(Vec<RoleData>),
+    RoleRevoked(Vec<RoleData>),
+    /// Switch between the open and the permissioned mode, see the `roles` module.
+    StampingModeChanged(Vec<StampingModeData>),
+    /// See the `owner` module.
+    OwnershipProposed(Vec<OwnershipProposedData>),
+    OwnershipTransferred(Vec<OwnershipTransferredData>),
+    Paused(Vec<PauseData>),
+    Unpaused(Vec<PauseData>),
+    /// See the `status` module.
+    StampRevoked(Vec<StampRevokedData>),
+    StampSuperseded(Vec<StampSupersededData>),
+    /// See the `revisions` module.
+    RevisionStamped(Vec<RevisionData>),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StampData {
+    /// Canonical file hash.
+    pub file_hash: String,
+    pub algorithm: String,
+    pub timestamp: U64,
+    pub block_height: U64,
+    pub stamper: AccountId,
+    /// Sequence number of the stamp in the stamp log.
+    pub seq: U64,
+    /// Hex encoded commitment of the record, only set when the record is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchRootData {
+    /// Hex encoded Merkle root.
+    pub root: String,
+    pub leaf_count: U64,
+    pub timestamp: U64,
+    pub block_height: U64,
+    pub stamper: AccountId,
+    /// Sequence number of the stamp in the stamp log.
+    pub seq: U64,
+    pub commitment: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StampRevokedData {
+    pub file_hash: String,
+    pub reason: String,
+    pub timestamp: U64,
+    pub stamper: AccountId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StampSupersededData {
+    pub file_hash: String,
+    /// Canonical file hash of the replacement.
+    pub superseded_by: String,
+    pub timestamp: U64,
+    pub stamper: AccountId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RevisionData {
+    pub file_hash: String,
+    pub parent_hash: String,
+    /// First version of the chain.
+    pub chain: String,
+    pub index: U64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleData {
+    pub role: Role,
+    pub account_id: AccountId,
+    /// Account that granted or revoked the role.
+    pub by: AccountId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StampingModeData {
+    pub permissioned: bool,
+    pub by: AccountId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnershipProposedData {
+    pub owner_id: AccountId,
+    /// `None` when the owner withdraws its proposal.
+    pub proposed_owner_id: Option<AccountId>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnershipTransferredData {
+    pub old_owner_id: AccountId,
+    pub new_owner_id: AccountId,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseData {
+    pub by: AccountId,
+}
+
+/// Events of NEP-171, see https://nomicon.io/Standards/Tokens/NonFungibleToken/Event
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum NftEvent {
+    NftMint(Vec<NftMintData>),
+    NftTransfer(Vec<NftTransferData>),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftMintData {
+    pub owner_id: AccountId,
+    pub token_ids: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftTransferData {
+    pub old_owner_id: AccountId,
+    pub new_owner_id: AccountId,
+    pub token_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a T,
+}
+
+impl Event {
+    pub fn to_log_string(&self) -> String {
+        log_string(EVENT_STANDARD, EVENT_VERSION, self)
+    }
+
+    pub fn emit(&self) {
+        emit(self.to_log_string());
+    }
+}
+
+impl NftEvent {
+    pub fn to_log_string(&self) -> String {
+        log_string(NFT_EVENT_STANDARD, NFT_EVENT_VERSION, self)
+    }
+
+    pub fn emit(&self) {
+        emit(self.to_log_string());
+    }
+}
+
+fn log_string<T: Serialize>(standard: &'static str, version: &'static str, event: &T) -> String {
+    let log = EventLog { standard, version, event };
+    format!("{}{}", EVENT_JSON_PREFIX, serde_json::to_string(&log).unwrap())
+}
+
+fn emit(log: String) {
+    env::log(log.as_bytes());
+}
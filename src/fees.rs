@@ -0,0 +1,104 @@
+//! Stamping fees, set by the owner and collected into a treasury it withdraws from.
+//!
+//! The fee is paid out of the attached deposit, before the storage the stamp adds: whatever is
+//! left of the deposit after the fee goes to storage as described in the `storage` module. The
+//! fee is kept separate from storage: accounts that prepaid their storage with
+//! `storage_deposit` still attach the fee to every stamp. A
+//! batch root is priced as a base fee plus a fee per leaf, so that batching can be offered at a
+//! discount over stamping every file hash on its own. Only `stamp_batch`, which hashes the
+//! leaves on chain, is priced per leaf: the contract can't check the `leaf_count` declared to
+//! `stamp_merkle_root`, which pays the flat `batch_root_fee` whatever the size of its tree.
+
+use crate::ProofOfTimestamp;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, ext_contract, near_bindgen, Balance, Gas, Promise, PromiseResult};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+const GAS_FOR_ON_FEES_WITHDRAWN: Gas = 5_000_000_000_000;
+
+#[ext_contract(ext_self)]
+pub trait SelfCallbacks {
+    fn on_fees_withdrawn(&mut self, amount: U128);
+}
+
+/// Fees in yoctoNEAR. Every fee is zero until the owner sets them.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSchedule {
+    /// Fee of `stamp`.
+    pub stamp_fee: U128,
+    /// Fee of a batch root. The whole fee of `stamp_merkle_root`.
+    pub batch_root_fee: U128,
+    /// Fee of each leaf of `stamp_batch`, on top of `batch_root_fee`.
+    pub batch_leaf_fee: U128,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self { stamp_fee: U128(0), batch_root_fee: U128(0), batch_leaf_fee: U128(0) }
+    }
+}
+
+impl FeeSchedule {
+    /// Fee of a batch of `leaf_count` file hashes hashed on chain.
+    pub fn batch_fee(&self, leaf_count: u64) -> Balance {
+        Balance::from(leaf_count).saturating_mul(self.batch_leaf_fee.0).saturating_add(self.batch_root_fee.0)
+    }
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Owner only.
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.assert_owner();
+        self.fee_schedule = fee_schedule;
+    }
+
+    pub fn get_fee_schedule(&self) -> FeeSchedule {
+        self.fee_schedule.clone()
+    }
+
+    /// Fees collected and not withdrawn yet.
+    pub fn get_collected_fees(&self) -> U128 {
+        self.collected_fees.into()
+    }
+
+    /// Sends `amount` of the collected fees to `receiver_id`. The fees are credited back if the
+    /// transfer fails. Owner only.
+    pub fn withdraw_fees(&mut self, amount: U128, receiver_id: ValidAccountId) -> Promise {
+        self.assert_owner();
+        if amount.0 > self.collected_fees {
+            env::panic(format!("Can't withdraw {} yoctoNEAR, {} were collected", amount.0, self.collected_fees).as_bytes());
+        }
+        self.collected_fees -= amount.0;
+        Promise::new(receiver_id.into())
+            .transfer(amount.0)
+            .then(ext_self::on_fees_withdrawn(amount, &env::current_account_id(), 0, GAS_FOR_ON_FEES_WITHDRAWN))
+    }
+
+    /// Callback of `withdraw_fees`.
+    pub fn on_fees_withdrawn(&mut self, amount: U128) {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Method is private");
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+        self.collected_fees += amount.0;
+        env::log(format!("Withdrawal of {} yoctoNEAR failed, fees credited back", amount.0).as_bytes());
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Takes `fee` out of the attached deposit and returns what is left of it.
+    pub(crate) fn collect_fee(&mut self, fee: Balance) -> Balance {
+        let deposit = env::attached_deposit();
+        if deposit < fee {
+            env::panic(format!("Must attach the fee of {} yoctoNEAR, attached {}", fee, deposit).as_bytes());
+        }
+        self.collected_fees += fee;
+        deposit - fee
+    }
+}
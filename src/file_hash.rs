@@ -0,0 +1,373 @@
+//! Parsing and normalization of the file hashes accepted by `stamp`.
+//!
+//! A file hash is an algorithm tag followed by the digest, e.g. `sha256:9f86d0...`.
+//! Accepted tags are `sha256`, `sha3-256`, `keccak256` and `blake2b-256` with a hex digest,
+//! `multihash` with a hex encoded multihash, and `cid` with a CIDv0 or CIDv1 string.
+//! Every accepted hash has a single canonical form, which is what the contract stores:
+//! lower-case hex for plain digests and multihashes (a multihash is unwrapped to its
+//! algorithm tag), and base32 CIDv1 for CIDs.
+
+use std::fmt;
+
+/// Longest file hash string accepted, checked before any decoding.
+pub const MAX_FILE_HASH_LEN: usize = 256;
+
+/// Multicodec of the `dag-pb` content type, implied by CIDv0.
+const DAG_PB_CODEC: u64 = 0x70;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha3_256,
+    Keccak256,
+    Blake2b256,
+}
+
+impl HashAlgorithm {
+    const ALL: [HashAlgorithm; 4] = [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha3_256,
+        HashAlgorithm::Keccak256,
+        HashAlgorithm::Blake2b256,
+    ];
+
+    pub fn tag(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Keccak256 => "keccak256",
+            HashAlgorithm::Blake2b256 => "blake2b-256",
+        }
+    }
+
+    pub fn digest_len(self) -> usize {
+        32
+    }
+
+    /// Code of the algorithm in the multihash table.
+    fn multihash_code(self) -> u64 {
+        match self {
+            HashAlgorithm::Sha256 => 0x12,
+            HashAlgorithm::Sha3_256 => 0x16,
+            HashAlgorithm::Keccak256 => 0x1b,
+            HashAlgorithm::Blake2b256 => 0xb220,
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|algorithm| algorithm.tag() == tag)
+    }
+
+    fn from_multihash_code(code: u64) -> Option<Self> {
+        Self::ALL.iter().copied().find(|algorithm| algorithm.multihash_code() == code)
+    }
+}
+
+/// A validated file hash.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileHash {
+    Digest { algorithm: HashAlgorithm, digest: Vec<u8> },
+    Cid { codec: u64, algorithm: HashAlgorithm, digest: Vec<u8> },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FileHashError {
+    Empty,
+    TooLong(usize),
+    MissingAlgorithm,
+    UnknownAlgorithm(String),
+    InvalidEncoding(&'static str),
+    InvalidDigestLength { algorithm: &'static str, expected: usize, actual: usize },
+    UnsupportedMultihash(u64),
+    InvalidCid(&'static str),
+}
+
+impl fmt::Display for FileHashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileHashError::Empty => write!(f, "File hash is empty"),
+            FileHashError::TooLong(len) => {
+                write!(f, "File hash is {} bytes long, at most {} are accepted", len, MAX_FILE_HASH_LEN)
+            }
+            FileHashError::MissingAlgorithm => {
+                write!(f, "File hash must be tagged with its algorithm, e.g. 'sha256:<hex digest>'")
+            }
+            FileHashError::UnknownAlgorithm(tag) => write!(
+                f,
+                "Unknown hash algorithm '{}', expected one of sha256, sha3-256, keccak256, blake2b-256, multihash, cid",
+                tag
+            ),
+            FileHashError::InvalidEncoding(expected) => write!(f, "File hash digest is not valid {}", expected),
+            FileHashError::InvalidDigestLength { algorithm, expected, actual } => write!(
+                f,
+                "A {} digest is {} bytes long, got {} bytes",
+                algorithm, expected, actual
+            ),
+            FileHashError::UnsupportedMultihash(code) => write!(f, "Unsupported multihash code 0x{:x}", code),
+            FileHashError::InvalidCid(reason) => write!(f, "Invalid CID: {}", reason),
+        }
+    }
+}
+
+impl FileHash {
+    pub fn parse(input: &str) -> Result<Self, FileHashError> {
+        if input.is_empty() {
+            return Err(FileHashError::Empty);
+        }
+        if input.len() > MAX_FILE_HASH_LEN {
+            return Err(FileHashError::TooLong(input.len()));
+        }
+        let separator = input.find(':').ok_or(FileHashError::MissingAlgorithm)?;
+        let (tag, value) = (input[..separator].to_ascii_lowercase(), &input[separator + 1..]);
+        match tag.as_str() {
+            "multihash" => {
+                let bytes = decode_hex(value)?;
+                let (algorithm, digest) = decode_multihash(&bytes).map_err(|err| match err {
+                    FileHashError::InvalidCid(_) => FileHashError::InvalidEncoding("multihash"),
+                    err => err,
+                })?;
+                Ok(FileHash::Digest { algorithm, digest })
+            }
+            "cid" => parse_cid(value),
+            _ => {
+                let algorithm = HashAlgorithm::from_tag(&tag).ok_or(FileHashError::UnknownAlgorithm(tag))?;
+                let digest = decode_hex(value)?;
+                check_digest_len(algorithm, &digest)?;
+                Ok(FileHash::Digest { algorithm, digest })
+            }
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            FileHash::Digest { algorithm, .. } | FileHash::Cid { algorithm, .. } => *algorithm,
+        }
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        match self {
+            FileHash::Digest { digest, .. } | FileHash::Cid { digest, .. } => digest,
+        }
+    }
+
+    /// The form under which the hash is stored and committed to.
+    pub fn canonical(&self) -> String {
+        match self {
+            FileHash::Digest { algorithm, digest } => format!("{}:{}", algorithm.tag(), encode_hex(digest)),
+            FileHash::Cid { codec, algorithm, digest } => {
+                let mut bytes = vec![];
+                write_varint(&mut bytes, 1);
+                write_varint(&mut bytes, *codec);
+                write_varint(&mut bytes, algorithm.multihash_code());
+                write_varint(&mut bytes, digest.len() as u64);
+                bytes.extend(digest);
+                format!("cid:b{}", encode_base32(&bytes))
+            }
+        }
+    }
+}
+
+fn check_digest_len(algorithm: HashAlgorithm, digest: &[u8]) -> Result<(), FileHashError> {
+    if digest.len() != algorithm.digest_len() {
+        return Err(FileHashError::InvalidDigestLength {
+            algorithm: algorithm.tag(),
+            expected: algorithm.digest_len(),
+            actual: digest.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Parses `<code varint><length varint><digest>`, all of the input must be consumed.
+fn decode_multihash(bytes: &[u8]) -> Result<(HashAlgorithm, Vec<u8>), FileHashError> {
+    let mut input = bytes;
+    let code = read_varint(&mut input)?;
+    let len = read_varint(&mut input)?;
+    let algorithm = HashAlgorithm::from_multihash_code(code).ok_or(FileHashError::UnsupportedMultihash(code))?;
+    if len != input.len() as u64 {
+        return Err(FileHashError::InvalidDigestLength {
+            algorithm: algorithm.tag(),
+            expected: len as usize,
+            actual: input.len(),
+        });
+    }
+    check_digest_len(algorithm, input)?;
+    Ok((algorithm, input.to_vec()))
+}
+
+fn parse_cid(value: &str) -> Result<FileHash, FileHashError> {
+    if value.len() == 46 && value.starts_with("Qm") {
+        let bytes = near_sdk::bs58::decode(value)
+            .into_vec()
+            .map_err(|_| FileHashError::InvalidEncoding("base58btc"))?;
+        let (algorithm, digest) = decode_multihash(&bytes)?;
+        return Ok(FileHash::Cid { codec: DAG_PB_CODEC, algorithm, digest });
+    }
+    let mut chars = value.chars();
+    let bytes = match chars.next() {
+        Some('b') | Some('B') => decode_base32(chars.as_str())?,
+        Some('z') => near_sdk::bs58::decode(chars.as_str())
+            .into_vec()
+            .map_err(|_| FileHashError::InvalidEncoding("base58btc"))?,
+        Some('f') | Some('F') => decode_hex(chars.as_str())?,
+        _ => return Err(FileHashError::InvalidCid("unsupported multibase, expected base32, base58btc or hex")),
+    };
+    let mut input = bytes.as_slice();
+    if read_varint(&mut input)? != 1 {
+        return Err(FileHashError::InvalidCid("unsupported version"));
+    }
+    let codec = read_varint(&mut input)?;
+    let (algorithm, digest) = decode_multihash(input)?;
+    Ok(FileHash::Cid { codec, algorithm, digest })
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn decode_hex(value: &str) -> Result<Vec<u8>, FileHashError> {
+    if !value.len().is_multiple_of(2) || !value.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(FileHashError::InvalidEncoding("hex"));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| FileHashError::InvalidEncoding("hex")))
+        .collect()
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// RFC 4648 base32, lower-case and without padding, as used by multibase.
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn decode_base32(value: &str) -> Result<Vec<u8>, FileHashError> {
+    let mut out = vec![];
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in value.bytes() {
+        let index = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_lowercase())
+            .ok_or(FileHashError::InvalidEncoding("base32"))?;
+        buffer = (buffer << 5) | index as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    // Leftover bits are padding and must be zero, otherwise two strings would decode the same
+    if buffer & ((1 << bits) - 1) != 0 {
+        return Err(FileHashError::InvalidEncoding("base32"));
+    }
+    Ok(out)
+}
+
+/// Reads an unsigned LEB128 varint, rejecting non-minimal encodings.
+fn read_varint(input: &mut &[u8]) -> Result<u64, FileHashError> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *input.get(i).ok_or(FileHashError::InvalidCid("truncated varint"))?;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            if byte == 0 && i > 0 {
+                return Err(FileHashError::InvalidCid("non-minimal varint"));
+            }
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(FileHashError::InvalidCid("varint too long"))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_HEX: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    // CIDv0 and CIDv1 of the same dag-pb node
+    const CID_V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const CID_V1: &str = "bafybeie5nqv6kd3qnfjupgvz34woh3oksc3iau6abmyajn7qvtf6d2ho34";
+
+    #[test]
+    fn normalizes_hex_case_and_tag() {
+        let lower = FileHash::parse(&format!("sha256:{}", SHA256_HEX)).unwrap();
+        let upper = FileHash::parse(&format!("SHA256:{}", SHA256_HEX.to_uppercase())).unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(format!("sha256:{}", SHA256_HEX), upper.canonical());
+        for tag in ["sha3-256", "keccak256", "blake2b-256"].iter() {
+            let hash = FileHash::parse(&format!("{}:{}", tag, SHA256_HEX)).unwrap();
+            assert_eq!(format!("{}:{}", tag, SHA256_HEX), hash.canonical());
+        }
+    }
+
+    #[test]
+    fn unwraps_multihash() {
+        let multihash = FileHash::parse(&format!("multihash:1220{}", SHA256_HEX)).unwrap();
+        assert_eq!(format!("sha256:{}", SHA256_HEX), multihash.canonical());
+        let blake2b = FileHash::parse(&format!("multihash:a0e40220{}", SHA256_HEX)).unwrap();
+        assert_eq!(HashAlgorithm::Blake2b256, blake2b.algorithm());
+        assert_eq!(
+            Err(FileHashError::UnsupportedMultihash(0x11)),
+            FileHash::parse(&format!("multihash:1114{}", &SHA256_HEX[..40]))
+        );
+        assert_eq!(
+            Err(FileHashError::InvalidDigestLength { algorithm: "sha256", expected: 31, actual: 32 }),
+            FileHash::parse(&format!("multihash:121f{}", SHA256_HEX))
+        );
+    }
+
+    #[test]
+    fn normalizes_cids_to_base32_v1() {
+        let v0 = FileHash::parse(&format!("cid:{}", CID_V0)).unwrap();
+        let v1 = FileHash::parse(&format!("cid:{}", CID_V1)).unwrap();
+        assert_eq!(v0, v1);
+        assert_eq!(format!("cid:{}", CID_V1), v0.canonical());
+        assert_eq!(v1, FileHash::parse(&format!("cid:{}", CID_V1.to_uppercase())).unwrap());
+        assert_eq!(
+            Err(FileHashError::InvalidCid("unsupported multibase, expected base32, base58btc or hex")),
+            FileHash::parse("cid:mAXASIA")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert_eq!(Err(FileHashError::Empty), FileHash::parse(""));
+        assert_eq!(Err(FileHashError::TooLong(1_000_000)), FileHash::parse(&"a".repeat(1_000_000)));
+        assert_eq!(Err(FileHashError::MissingAlgorithm), FileHash::parse(SHA256_HEX));
+        assert_eq!(
+            Err(FileHashError::UnknownAlgorithm("md5".to_string())),
+            FileHash::parse("md5:d41d8cd98f00b204e9800998ecf8427e")
+        );
+        assert_eq!(
+            Err(FileHashError::InvalidEncoding("hex")),
+            FileHash::parse(&format!("sha256:{}", SHA256_HEX.replace("9", "g")))
+        );
+        assert_eq!(
+            Err(FileHashError::InvalidDigestLength { algorithm: "sha256", expected: 32, actual: 31 }),
+            FileHash::parse(&format!("sha256:{}", &SHA256_HEX[2..]))
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Paying for stamps with NEP-141 fungible tokens.
+//!
+//! The owner allowlists a token by setting its fee schedule, in units of that token. Holders
+//! then stamp with `ft_transfer_call` to this contract, the `msg` naming the file hashes:
+//!
+//! ```text
+//! {"file_hashes": ["sha256:...", ...]}                 each hash is stamped, at the stamp fee each
+//! {"file_hashes": ["sha256:...", ...], "batch": true}  the Merkle root of the hashes is stamped, see `stamp_batch`
+//! ```
+//!
+//! The sender of the tokens is the stamper. Tokens left after the fee are handed back through
+//! the return value of `ft_on_transfer`, and a failed stamp refunds them all. No NEAR comes
+//! with a token transfer, so the stamper must have prepaid its storage with `storage_deposit`.
+
+use crate::fees::FeeSchedule;
+use crate::{ProofOfTimestamp, MAX_BATCH_SIZE};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, Gas, Promise, PromiseOrValue, PromiseResult};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_TOKEN_FEES_WITHDRAWN: Gas = 5_000_000_000_000;
+
+/// `msg` of an `ft_transfer_call` to this contract.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtStampMessage {
+    pub file_hashes: Vec<String>,
+    /// Stamp the Merkle root of `file_hashes` rather than each of them.
+    #[serde(default)]
+    pub batch: bool,
+}
+
+#[ext_contract(ext_fungible_token)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+#[ext_contract(ext_self)]
+pub trait SelfCallbacks {
+    fn on_token_fees_withdrawn(&mut self, token_id: AccountId, amount: U128);
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// NEP-141 receiver, stamps the file hashes named in `msg` for `sender_id` and returns the
+    /// amount of tokens to refund.
+    pub fn ft_on_transfer(&mut self, sender_id: ValidAccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        let fee_schedule = self
+            .token_fee_schedules
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic(format!("Token '{}' is not accepted", token_id).as_bytes()));
+        let stamper: AccountId = sender_id.into();
+        self.assert_can_stamp(&stamper);
+        let message: FtStampMessage = serde_json::from_str(&msg)
+            .unwrap_or_else(|err| env::panic(format!("Invalid ft_transfer_call message: {}", err).as_bytes()));
+        assert!(
+            self.storage_accounts.contains_key(&stamper),
+            "'{}' must prepay its storage with storage_deposit to stamp with tokens",
+            stamper
+        );
+        let hash_count = message.file_hashes.len();
+        let fee = if message.batch {
+            fee_schedule.batch_fee(hash_count as u64)
+        } else {
+            assert!(hash_count > 0, "Message names no file hash");
+            assert!(hash_count <= MAX_BATCH_SIZE, "Message names {} file hashes, at most {} are accepted", hash_count, MAX_BATCH_SIZE);
+            Balance::from(hash_count as u64).saturating_mul(fee_schedule.stamp_fee.0)
+        };
+        if amount.0 < fee {
+            env::panic(format!("Must transfer the fee of {} tokens, transferred {}", fee, amount.0).as_bytes());
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        if message.batch {
+            self.stamp_batch_root(&message.file_hashes, &stamper);
+        } else {
+            for file_hash in &message.file_hashes {
+                self.stamp_file_hash(file_hash, &stamper);
+            }
+        }
+        self.charge_storage(&stamper, 0, initial_storage_usage);
+        let collected_fees = self.collected_token_fees.get(&token_id).unwrap_or(0);
+        self.collected_token_fees.insert(&token_id, &(collected_fees + fee));
+        PromiseOrValue::Value((amount.0 - fee).into())
+    }
+
+    /// Accepts `token_id` for stamping at `fee_schedule`, in units of the token. Owner only.
+    pub fn set_token_fee_schedule(&mut self, token_id: ValidAccountId, fee_schedule: FeeSchedule) {
+        self.assert_owner();
+        self.token_fee_schedules.insert(token_id.as_ref(), &fee_schedule);
+    }
+
+    /// Stops accepting `token_id`, its collected fees can still be withdrawn. Owner only.
+    pub fn remove_token(&mut self, token_id: ValidAccountId) -> bool {
+        self.assert_owner();
+        self.token_fee_schedules.remove(token_id.as_ref()).is_some()
+    }
+
+    pub fn get_token_fee_schedule(&self, token_id: ValidAccountId) -> Option<FeeSchedule> {
+        self.token_fee_schedules.get(token_id.as_ref())
+    }
+
+    /// Returns up to `limit` accepted tokens and their fee schedules, starting at index `from`.
+    pub fn get_token_fee_schedules(&self, from: u64, limit: u64) -> Vec<(AccountId, FeeSchedule)> {
+        let (tokens, fee_schedules) = (self.token_fee_schedules.keys_as_vector(), self.token_fee_schedules.values_as_vector());
+        (from..std::cmp::min(from.saturating_add(limit), tokens.len()))
+            .map(|index| (tokens.get(index).unwrap(), fee_schedules.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Fees collected in `token_id` and not withdrawn yet.
+    pub fn get_collected_token_fees(&self, token_id: ValidAccountId) -> U128 {
+        self.collected_token_fees.get(token_id.as_ref()).unwrap_or(0).into()
+    }
+
+    /// Transfers `amount` of the fees collected in `token_id` to `receiver_id`, which must be
+    /// registered with the token. The fees are credited back if the transfer fails. Owner only.
+    pub fn withdraw_token_fees(&mut self, token_id: ValidAccountId, amount: U128, receiver_id: ValidAccountId) -> Promise {
+        self.assert_owner();
+        let token_id: AccountId = token_id.into();
+        let collected_fees = self.collected_token_fees.get(&token_id).unwrap_or(0);
+        if amount.0 > collected_fees {
+            env::panic(format!("Can't withdraw {} tokens, {} were collected", amount.0, collected_fees).as_bytes());
+        }
+        self.collected_token_fees.insert(&token_id, &(collected_fees - amount.0));
+        ext_fungible_token::ft_transfer(receiver_id.into(), amount, None, &token_id, 1, GAS_FOR_FT_TRANSFER).then(
+            ext_self::on_token_fees_withdrawn(
+                token_id.clone(),
+                amount,
+                &env::current_account_id(),
+                0,
+                GAS_FOR_ON_TOKEN_FEES_WITHDRAWN,
+            ),
+        )
+    }
+
+    /// Callback of `withdraw_token_fees`.
+    pub fn on_token_fees_withdrawn(&mut self, token_id: AccountId, amount: U128) {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Method is private");
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return;
+        }
+        let collected_fees = self.collected_token_fees.get(&token_id).unwrap_or(0);
+        self.collected_token_fees.insert(&token_id, &(collected_fees + amount.0));
+        env::log(format!("Withdrawal of {} '{}' tokens failed, fees credited back", amount.0, token_id).as_bytes());
+    }
+}
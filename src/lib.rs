@@ -1,9 +1,32 @@
 /*
  * This is a proof of timestamp rust smart contract with two functions:
  *
- * 1. stamp: accepts a file hash, gets the current block timestamp, concatenates both variables and records their hash into the blockchain
- * 2. get_stamp: accepts file hash and returns the timestamp saved for it, defaulting to
- *    TimestampedFile { timestamp: 0, time_stamped_file_hash: [] }
+ * 1. stamp: accepts an algorithm tagged file hash (see the `file_hash` module), gets the current block timestamp and records
+ *    a commitment to both into the blockchain (see the `commitment` module).
+ *    The first stamp of a file hash wins, stamping it again only adds an observation to its history
+ * 2. get_stamp: accepts file hash and returns the timestamp saved for it along with the account and block that stamped it,
+ *    or null if the file hash was never stamped. is_stamped only tells whether it was. get_stamp_borsh returns the raw
+ *    Borsh encoded record for contract-to-contract callers
+ * 3. stamp_batch / stamp_merkle_root: stamp the Merkle root of many file hashes at once (see the `merkle` module), and
+ *    get_batched_stamp resolves a file hash and its inclusion proof to the timestamp of its root. verify_inclusion does
+ *    the same from a leaf hash, for verifiers that build leaves themselves
+ * 4. get_first_stamp / get_stamp_history: return who stamped a file hash, when and at which block.
+ *    get_stamps_by_account / count_stamps_by_account list what an account stamped (see the `account_index` module), and
+ *    get_stamps_between / get_account_stamps_between what was stamped over a time range (see the `time_index` module).
+ *    get_log_head / get_log_entry / get_log_segment read the hash-chained log of every stamp (see the `stamp_log` module),
+ *    and get_tree_head / get_inclusion_proof / get_consistency_proof the Merkle tree over it (see the `transparency_log` module)
+ * 5. revoke_stamp / supersede_stamp: let the original stamper mark its stamp as withdrawn or replaced (see the `status`
+ *    module). stamp_revision / get_revision / get_revision_chain track the versions of a document (see the `revisions` module)
+ * 6. new / migrate: initialize the contract, or upgrade it from the original in-memory HashMap state, whose records
+ *    migrate_batch then moves into persistent storage a batch at a time. Both set the owner, who can pause stamping (see the `owner` module)
+ *    and restrict it to notaries (see the `roles` module)
+ *
+ * Every stamp is announced with a NEP-297 event, see the `events` module. Stamping methods are payable: the attached
+ * deposit must cover the stamping fee set by the owner (see the `fees` module) and the storage the stamp adds, and the
+ * rest of it is refunded, unless the stamper prepaid its storage with the NEP-145 storage management methods, see the
+ * `storage` module. Stamps can also be paid with fungible tokens through ft_transfer_call, see the `ft_payments` module.
+ * The owner can have every new record minted as a NEP-171 certificate to its stamper, see the `nft` module.
+ * Outside of wasm, the `verifier` module checks records, proofs and log segments offline.
  *
  * Learn more about proof of timestamp:
  * https://en.wikipedia.org/wiki/Trusted_timestamping
@@ -12,42 +35,632 @@
  */
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, TreeMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::serde::Serialize;
+use near_sdk::json_types::ValidAccountId;
 use near_sdk::wee_alloc;
-use near_sdk::{env, near_bindgen};
+use near_sdk::{env, near_bindgen, AccountId, Balance, BlockHeight, EpochHeight};
 use std::collections::HashMap;
 
+// Modules below add `#[near_bindgen]` methods to the contract. On wasm those methods refer to the
+// blockchain interface as `near_blockchain`, which only the crate root has in scope, so each of
+// them imports it with `#[cfg(target_arch = "wasm32")] use crate::near_blockchain;`.
+pub mod account_index;
+pub mod commitment;
+pub mod events;
+pub mod fees;
+pub mod ft_payments;
+pub mod file_hash;
+pub mod merkle;
+pub mod nft;
+pub mod owner;
+pub mod revisions;
+pub mod roles;
+pub mod stamp_log;
+pub mod status;
+pub mod storage;
+pub mod time_index;
+pub mod transparency_log;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod verifier;
+pub mod views;
+use commitment::COMMITMENT_VERSION;
+use events::{BatchRootData, Event, StampData};
+use fees::FeeSchedule;
+use file_hash::{decode_hex, encode_hex, FileHash};
+use merkle::{MerkleProof, Side};
+use nft::TokenId;
+use revisions::RevisionPosition;
+use roles::Role;
+use stamp_log::LogEntry;
+use status::StampStatus;
+use storage::StorageAccount;
+use time_index::TimeKey;
+use transparency_log::NodePosition;
+use views::{BatchRootView, TimestampedFileView};
+
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+/// Storage key prefix of `ProofOfTimestamp::records`.
+const RECORDS_PREFIX: &[u8] = b"r";
+/// Storage key prefix of `ProofOfTimestamp::history`.
+const HISTORY_PREFIX: &[u8] = b"h";
+/// Storage key prefix of the per file hash observation vectors stored in `history`.
+const OBSERVATIONS_PREFIX: &[u8] = b"o";
+/// Storage key prefix of `ProofOfTimestamp::batch_roots`.
+const BATCH_ROOTS_PREFIX: &[u8] = b"b";
+/// Storage key prefix of `ProofOfTimestamp::storage_accounts`.
+const STORAGE_ACCOUNTS_PREFIX: &[u8] = b"a";
+/// Storage key prefix of `ProofOfTimestamp::roles`.
+const ROLES_PREFIX: &[u8] = b"l";
+/// Storage key prefix of the per role member sets stored in `roles`.
+const ROLE_MEMBERS_PREFIX: &[u8] = b"m";
+/// Storage key prefix of `ProofOfTimestamp::token_fee_schedules`.
+const TOKEN_FEE_SCHEDULES_PREFIX: &[u8] = b"t";
+/// Storage key prefix of `ProofOfTimestamp::collected_token_fees`.
+const COLLECTED_TOKEN_FEES_PREFIX: &[u8] = b"f";
+/// Storage key prefix of `ProofOfTimestamp::certificates`.
+const CERTIFICATES_PREFIX: &[u8] = b"c";
+/// Storage key prefix of `ProofOfTimestamp::certificates_per_owner`.
+const CERTIFICATES_PER_OWNER_PREFIX: &[u8] = b"n";
+/// Storage key prefix of the per account certificate sets stored in `certificates_per_owner`.
+const OWNER_CERTIFICATES_PREFIX: &[u8] = b"p";
+/// Storage key prefix of `ProofOfTimestamp::statuses`.
+const STATUSES_PREFIX: &[u8] = b"s";
+/// Storage key prefix of `ProofOfTimestamp::revisions`.
+const REVISIONS_PREFIX: &[u8] = b"v";
+/// Storage key prefix of `ProofOfTimestamp::revision_chains`.
+const REVISION_CHAINS_PREFIX: &[u8] = b"e";
+/// Storage key prefix of the per chain version vectors stored in `revision_chains`.
+const REVISION_CHAIN_VERSIONS_PREFIX: &[u8] = b"x";
+/// Storage key prefix of `ProofOfTimestamp::account_stamps`.
+const ACCOUNT_STAMPS_INDEX_PREFIX: &[u8] = b"k";
+/// Storage key prefix of the per account stamp vectors stored in `account_stamps`.
+const ACCOUNT_STAMPS_PREFIX: &[u8] = b"y";
+/// Storage key prefix of `ProofOfTimestamp::time_index`.
+const TIME_INDEX_PREFIX: &[u8] = b"z";
+/// Storage key prefix of `ProofOfTimestamp::log`.
+const LOG_PREFIX: &[u8] = b"g";
+/// Storage key prefix of `ProofOfTimestamp::tree_nodes`.
+const TREE_NODES_PREFIX: &[u8] = b"d";
+/// Storage key of the legacy records `migrate` stashes for `migrate_batch`, oldest first.
+const LEGACY_RECORDS_KEY: &[u8] = b"q";
+/// Most legacy records `migrate_batch` moves in one call, which stays well within the gas
+/// limit of a transaction.
+pub const MAX_MIGRATION_BATCH: u64 = 100;
+/// Most file hashes `stamp_batch` hashes on chain, larger batches go through `stamp_merkle_root`.
+pub const MAX_BATCH_SIZE: usize = 256;
+
 #[near_bindgen]
-#[derive(Default, BorshDeserialize, BorshSerialize)]
+#[derive(BorshDeserialize, BorshSerialize)]
 pub struct ProofOfTimestamp {
-    records: HashMap<String, TimestampedFile>,
+    records: LookupMap<String, VersionedTimestampedFile>,
+    history: LookupMap<String, Vector<StampObservation>>,
+    batch_roots: LookupMap<String, BatchRoot>,
+    storage_accounts: LookupMap<AccountId, StorageAccount>,
+    /// See the `owner` module.
+    owner_id: AccountId,
+    proposed_owner_id: Option<AccountId>,
+    paused: bool,
+    /// Members of each role, see the `roles` module.
+    roles: LookupMap<Role, UnorderedSet<AccountId>>,
+    /// Whether only notaries can stamp.
+    permissioned: bool,
+    /// See the `fees` module.
+    fee_schedule: FeeSchedule,
+    collected_fees: Balance,
+    /// Fungible tokens accepted as payment, see the `ft_payments` module.
+    token_fee_schedules: UnorderedMap<AccountId, FeeSchedule>,
+    collected_token_fees: LookupMap<AccountId, Balance>,
+    /// Certificate NFTs, see the `nft` module.
+    certificates_enabled: bool,
+    /// Holder of each certificate, by token ID.
+    certificates: LookupMap<TokenId, AccountId>,
+    certificates_per_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+    certificate_count: u64,
+    /// Status of the records that were revoked or superseded, see the `status` module.
+    statuses: LookupMap<String, StampStatus>,
+    /// Revision chains, see the `revisions` module.
+    revisions: LookupMap<String, RevisionPosition>,
+    /// Versions of each chain, by first version.
+    revision_chains: LookupMap<String, Vector<String>>,
+    /// Stamps of each account, see the `account_index` module.
+    account_stamps: LookupMap<AccountId, Vector<u64>>,
+    /// Stamps by time, see the `time_index` module.
+    time_index: TreeMap<TimeKey, ()>,
+    /// Every stamp, by sequence number, see the `stamp_log` module.
+    log: Vector<LogEntry>,
+    /// Roots of the perfect subtrees of the tree over `log`, see the `transparency_log` module.
+    tree_nodes: LookupMap<NodePosition, Vec<u8>>,
+    /// Legacy records `migrate_batch` has yet to move, the last ones stashed under
+    /// `LEGACY_RECORDS_KEY`. Stamping is blocked until they are all moved.
+    legacy_records_left: u64,
+}
+
+impl Default for ProofOfTimestamp {
+    fn default() -> Self {
+        env::panic(b"Contract should be initialized before usage")
+    }
+}
+
+/// Contract state as it was laid out before records moved to persistent storage.
+/// Only used by `migrate` to read the old state back.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyProofOfTimestamp {
+    records: HashMap<String, TimestampedFileV1>,
 }
 
-#[derive(Default, Clone,Debug,PartialEq, BorshDeserialize, BorshSerialize)]
+/// Record of a stamped file hash, as returned by `get_stamp`. The block and account fields
+/// are `None` for records stamped before they were tracked (see `TimestampedFileV1`).
+#[derive(Default, Clone,Debug,PartialEq, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
 pub struct TimestampedFile {
     timestamp: u64,
-    time_stamped_file_hash: Vec<u8>
+    time_stamped_file_hash: Vec<u8>,
+    /// Scheme of `time_stamped_file_hash`, see the `commitment` module.
+    commitment_version: u8,
+    /// Account that called `stamp`.
+    stamper: Option<AccountId>,
+    /// Account that signed the transaction, differs from `stamper` for cross-contract calls.
+    signer: Option<AccountId>,
+    block_height: Option<BlockHeight>,
+    epoch_height: Option<EpochHeight>,
+}
+
+/// Record layout of the first release, which only kept the timestamp and the commitment.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct TimestampedFileV1 {
+    timestamp: u64,
+    time_stamped_file_hash: Vec<u8>,
+}
+
+/// Record of the legacy state, with the file hash it was stamped under there.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct MigratedTimestampedFile {
+    /// File hash as stamped in the legacy state, which the version 0 commitment covers. It may
+    /// differ from the canonical form the record is stored under.
+    legacy_file_hash: String,
+    record: TimestampedFileV1,
+}
+
+/// Records are stored tagged with their layout version, so that records written by older
+/// releases keep decoding after the layout changes.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum VersionedTimestampedFile {
+    V1(MigratedTimestampedFile),
+    V2(TimestampedFile),
+}
+
+impl From<VersionedTimestampedFile> for TimestampedFile {
+    fn from(record: VersionedTimestampedFile) -> Self {
+        match record {
+            VersionedTimestampedFile::V1(MigratedTimestampedFile { record, .. }) => TimestampedFile {
+                timestamp: record.timestamp,
+                time_stamped_file_hash: record.time_stamped_file_hash,
+                ..Default::default()
+            },
+            VersionedTimestampedFile::V2(record) => record,
+        }
+    }
+}
+
+impl VersionedTimestampedFile {
+    /// File hash the commitment of a migrated record covers, if it isn't `key`, the key the
+    /// record is stored under.
+    fn legacy_file_hash(&self, key: &str) -> Option<String> {
+        match self {
+            VersionedTimestampedFile::V1(record) if record.legacy_file_hash != key => Some(record.legacy_file_hash.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// One stamp of a file hash. The first observation of a hash matches its `TimestampedFile`,
+/// the following ones are later stamps of the same hash, in the order they were made.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StampObservation {
+    /// Account that stamped, `None` for stamps migrated from the legacy state.
+    stamper: Option<AccountId>,
+    timestamp: u64,
+    /// Block height of the stamp, `None` for stamps migrated from the legacy state.
+    block_height: Option<BlockHeight>,
+    /// Sequence number of the stamp in the stamp log, `None` for stamps migrated from the
+    /// legacy state, which were never logged.
+    seq: Option<u64>,
+    /// File hash of a stamp migrated from the legacy state as it was stamped there, when it
+    /// differs from its canonical form. Its version 0 commitment covers this spelling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    legacy_file_hash: Option<String>,
+}
+
+/// A stamped Merkle root. Its record commits to the root key (see `batch_root_key`) in place
+/// of a file hash.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct BatchRoot {
+    record: TimestampedFile,
+    leaf_count: u64,
 }
 
 #[near_bindgen]
 impl ProofOfTimestamp {
+    /// Initializes the contract, owned by `owner_id`.
+    #[init]
+    pub fn new(owner_id: ValidAccountId) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self::empty(owner_id.into())
+    }
 
+    /// Records the current block timestamp for `file_hash`. The first stamp of a hash is
+    /// final: stamping an already stamped hash only appends an observation to its history
+    /// and leaves the original record untouched.
+    #[payable]
     pub fn stamp(&mut self, file_hash: String) {
+        let stamper = env::predecessor_account_id();
+        self.assert_can_stamp(&stamper);
+        let initial_storage_usage = env::storage_usage();
+        self.stamp_file_hash(&file_hash, &stamper);
+        let deposit = self.collect_fee(self.fee_schedule.stamp_fee.0);
+        self.charge_storage(&stamper, deposit, initial_storage_usage);
+    }
+
+    /// Stamps the root of a Merkle tree built off-chain with the `merkle` module over the leaf
+    /// hashes of `leaf_count` canonical file hashes. Like file hashes, a root can only be
+    /// stamped once. The declared `leaf_count` can't be checked, the fee is the flat
+    /// `batch_root_fee` of the fee schedule.
+    #[payable]
+    pub fn stamp_merkle_root(&mut self, root: String, leaf_count: u64) {
+        let stamper = env::predecessor_account_id();
+        self.assert_can_stamp(&stamper);
+        let initial_storage_usage = env::storage_usage();
+        let root = decode_hex(&root)
+            .ok()
+            .filter(|root| root.len() == 32)
+            .unwrap_or_else(|| env::panic(b"Merkle root must be a hex encoded 32 byte hash"));
+        assert!(leaf_count > 0, "{}", merkle::MerkleError::EmptyTree);
+        self.insert_batch_root(&root, leaf_count, &stamper);
+        let deposit = self.collect_fee(self.fee_schedule.batch_root_fee.0);
+        self.charge_storage(&stamper, deposit, initial_storage_usage);
+    }
+
+    /// Stamps the Merkle root of up to `MAX_BATCH_SIZE` file hashes, computed on chain, and
+    /// returns it hex encoded. Proofs for the file hashes are built off-chain with
+    /// `merkle::merkle_proof`, over the leaf hashes of the canonical file hashes in the same order.
+    #[payable]
+    pub fn stamp_batch(&mut self, file_hashes: Vec<String>) -> String {
+        let stamper = env::predecessor_account_id();
+        self.assert_can_stamp(&stamper);
+        let initial_storage_usage = env::storage_usage();
+        let root = self.stamp_batch_root(&file_hashes, &stamper);
+        let deposit = self.collect_fee(self.fee_schedule.batch_fee(file_hashes.len() as u64));
+        self.charge_storage(&stamper, deposit, initial_storage_usage);
+        encode_hex(&root)
+    }
+
+    pub fn get_batch_root(&self, root: String) -> Option<BatchRootView> {
+        let root = decode_hex(&root).unwrap_or_else(|err| env::panic(err.to_string().as_bytes()));
+        self.batch_root_view(&root)
+    }
+
+    /// Resolves `file_hash` and its inclusion proof to the stamped root of its batch, `None`
+    /// if the proof leads to a root that was never stamped.
+    pub fn get_batched_stamp(&self, file_hash: String, proof: MerkleProof) -> Option<BatchRootView> {
+        let leaf = merkle::leaf_hash(parse_file_hash(&file_hash).canonical().as_bytes());
+        let root = proof.compute_root(&leaf).unwrap_or_else(|err| env::panic(err.to_string().as_bytes()));
+        self.batch_root_view(&root)
+    }
+
+    /// Recomputes the root above the hex encoded `leaf_hash` (see `merkle::leaf_hash`) from
+    /// its sibling path, `sides[i]` telling on which side of the path `siblings[i]` is, and
+    /// returns the record of that root if it was stamped. Malformed proofs are rejected.
+    pub fn verify_inclusion(&self, leaf_hash: String, siblings: Vec<String>, sides: Vec<Side>) -> Option<TimestampedFileView> {
+        let leaf = decode_hex(&leaf_hash)
+            .ok()
+            .filter(|leaf| leaf.len() == 32)
+            .unwrap_or_else(|| env::panic(b"Leaf hash must be a hex encoded 32 byte hash"));
+        let root = MerkleProof { siblings, sides }
+            .compute_root(&leaf)
+            .unwrap_or_else(|err| env::panic(err.to_string().as_bytes()));
+        self.batch_root_view(&root).map(|batch_root| batch_root.stamp)
+    }
+
+    pub fn get_stamp(&self, file_hash: String) -> Option<TimestampedFileView> {
+        let file_hash = self.record_key(&file_hash);
+        self.records
+            .get(&file_hash)
+            .map(|record| {
+                let status = self.stamp_status(&file_hash);
+                let legacy_file_hash = record.legacy_file_hash(&file_hash);
+                TimestampedFileView { legacy_file_hash, ..TimestampedFileView::new(file_hash, record.into(), status) }
+            })
+    }
+
+    /// Same as `get_stamp`, returning the Borsh encoded record.
+    #[result_serializer(borsh)]
+    pub fn get_stamp_borsh(&self, file_hash: String) -> Option<TimestampedFile> {
+        let file_hash = self.record_key(&file_hash);
+        self.records.get(&file_hash).map(TimestampedFile::from)
+    }
+
+    pub fn is_stamped(&self, file_hash: String) -> bool {
+        let file_hash = self.record_key(&file_hash);
+        self.records.contains_key(&file_hash)
+    }
+
+    /// Returns the observation that created the record of `file_hash`, if it is stamped.
+    pub fn get_first_stamp(&self, file_hash: String) -> Option<StampObservation> {
+        let file_hash = self.record_key(&file_hash);
+        self.history.get(&file_hash).and_then(|observations| observations.get(0))
+    }
+
+    /// Returns up to `limit` observations of `file_hash`, oldest first, starting at index `from`.
+    pub fn get_stamp_history(&self, file_hash: String, from: u64, limit: u64) -> Vec<StampObservation> {
+        let file_hash = self.record_key(&file_hash);
+        match self.history.get(&file_hash) {
+            Some(observations) => (from..std::cmp::min(from.saturating_add(limit), observations.len()))
+                .map(|index| observations.get(index).unwrap())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Upgrades the legacy `HashMap` state: stashes its records, oldest first, for
+    /// `migrate_batch` to move into `records` and blocks stamping until they are all moved, so
+    /// that no new stamp takes the place of a legacy one. Until then, views only see the
+    /// records moved so far. The legacy state had no owner, the migrated contract is owned by
+    /// `owner_id`. Must be called by the contract account itself, in the same transaction that
+    /// deploys this version of the code over the old one. Running it again fails because the
+    /// state is no longer in the legacy layout.
+    #[init]
+    pub fn migrate(owner_id: ValidAccountId) -> Self {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Only the contract account can migrate its state"
+        );
+        let legacy: LegacyProofOfTimestamp = env::state_read().expect("No legacy state to migrate");
+        let mut contract = Self::empty(owner_id.into());
+        let mut stamps: Vec<_> = legacy.records.into_iter().collect();
+        stamps.sort_by(|(hash_a, stamp_a), (hash_b, stamp_b)| (stamp_a.timestamp, hash_a).cmp(&(stamp_b.timestamp, hash_b)));
+        contract.legacy_records_left = stamps.len() as u64;
+        if !stamps.is_empty() {
+            env::storage_write(LEGACY_RECORDS_KEY, &stamps.try_to_vec().unwrap());
+        }
+        env::log(format!("Stashed {} legacy records, move them with migrate_batch", stamps.len()).as_bytes());
+        contract
+    }
+
+    /// Moves up to `limit`, at most `MAX_MIGRATION_BATCH`, of the legacy records stashed by
+    /// `migrate` into `records`, keyed by the canonical form of their file hash, and returns
+    /// how many are left. Legacy hashes that don't parse are kept as is, and legacy hashes that
+    /// normalize to the same canonical hash are merged, the earliest one becoming the record and
+    /// the others observations. Both keep the legacy spelling their commitment covers. Owner
+    /// only.
+    pub fn migrate_batch(&mut self, limit: u64) -> u64 {
+        self.assert_owner();
+        if self.legacy_records_left == 0 {
+            return 0;
+        }
+        let stamps: Vec<(String, TimestampedFileV1)> =
+            BorshDeserialize::try_from_slice(&env::storage_read(LEGACY_RECORDS_KEY).unwrap()).unwrap();
+        let start = stamps.len() as u64 - self.legacy_records_left;
+        let end = std::cmp::min(start + std::cmp::min(limit, MAX_MIGRATION_BATCH), stamps.len() as u64);
+        for (legacy_file_hash, stamp) in &stamps[start as usize..end as usize] {
+            self.migrate_record(legacy_file_hash, stamp);
+        }
+        self.legacy_records_left -= end - start;
+        if self.legacy_records_left == 0 {
+            env::storage_remove(LEGACY_RECORDS_KEY);
+        }
+        env::log(format!("Migrated {} legacy records, {} left", end - start, self.legacy_records_left).as_bytes());
+        self.legacy_records_left
+    }
+
+    /// Legacy records `migrate_batch` has yet to move.
+    pub fn count_unmigrated_records(&self) -> u64 {
+        self.legacy_records_left
+    }
+}
+
+impl ProofOfTimestamp {
+    /// State without any stamp, owned by `owner_id`.
+    fn empty(owner_id: AccountId) -> Self {
+        Self {
+            records: LookupMap::new(RECORDS_PREFIX.to_vec()),
+            history: LookupMap::new(HISTORY_PREFIX.to_vec()),
+            batch_roots: LookupMap::new(BATCH_ROOTS_PREFIX.to_vec()),
+            storage_accounts: LookupMap::new(STORAGE_ACCOUNTS_PREFIX.to_vec()),
+            owner_id,
+            proposed_owner_id: None,
+            paused: false,
+            roles: LookupMap::new(ROLES_PREFIX.to_vec()),
+            permissioned: false,
+            fee_schedule: FeeSchedule::default(),
+            collected_fees: 0,
+            token_fee_schedules: UnorderedMap::new(TOKEN_FEE_SCHEDULES_PREFIX.to_vec()),
+            collected_token_fees: LookupMap::new(COLLECTED_TOKEN_FEES_PREFIX.to_vec()),
+            certificates_enabled: false,
+            certificates: LookupMap::new(CERTIFICATES_PREFIX.to_vec()),
+            certificates_per_owner: LookupMap::new(CERTIFICATES_PER_OWNER_PREFIX.to_vec()),
+            certificate_count: 0,
+            statuses: LookupMap::new(STATUSES_PREFIX.to_vec()),
+            revisions: LookupMap::new(REVISIONS_PREFIX.to_vec()),
+            revision_chains: LookupMap::new(REVISION_CHAINS_PREFIX.to_vec()),
+            account_stamps: LookupMap::new(ACCOUNT_STAMPS_INDEX_PREFIX.to_vec()),
+            time_index: TreeMap::new(TIME_INDEX_PREFIX.to_vec()),
+            log: Vector::new(LOG_PREFIX.to_vec()),
+            tree_nodes: LookupMap::new(TREE_NODES_PREFIX.to_vec()),
+            legacy_records_left: 0,
+        }
+    }
+
+    /// Blocks stamping while `migrate_batch` has legacy records left to move.
+    pub(crate) fn assert_migrated(&self) {
+        if self.legacy_records_left > 0 {
+            env::panic(format!("Stamping is blocked until the legacy records are migrated, {} are left", self.legacy_records_left).as_bytes());
+        }
+    }
+
+    fn migrate_record(&mut self, legacy_file_hash: &str, stamp: &TimestampedFileV1) {
+        let file_hash = FileHash::parse(legacy_file_hash).map(|hash| hash.canonical()).unwrap_or_else(|_| legacy_file_hash.to_string());
+        if !self.records.contains_key(&file_hash) {
+            let record = MigratedTimestampedFile { legacy_file_hash: legacy_file_hash.to_string(), record: stamp.clone() };
+            self.records.insert(&file_hash, &VersionedTimestampedFile::V1(record));
+        }
+        self.add_observation(
+            &file_hash,
+            &StampObservation {
+                stamper: None,
+                timestamp: stamp.timestamp,
+                block_height: None,
+                seq: None,
+                legacy_file_hash: Some(legacy_file_hash.to_string()).filter(|legacy_file_hash| *legacy_file_hash != file_hash),
+            },
+        );
+    }
+
+    /// Storage key of the record of `file_hash`: its canonical form, or the raw string for
+    /// records migrated from the legacy state whose hash doesn't parse.
+    fn record_key(&self, file_hash: &str) -> String {
+        match FileHash::parse(file_hash) {
+            Ok(hash) => hash.canonical(),
+            Err(_) if self.records.contains_key(&file_hash.to_string()) => file_hash.to_string(),
+            Err(err) => env::panic(err.to_string().as_bytes()),
+        }
+    }
+
+    /// Stamps `file_hash` for `stamper`, see `stamp`.
+    fn stamp_file_hash(&mut self, file_hash: &str, stamper: &AccountId) {
+        let parsed_hash = parse_file_hash(file_hash);
+        let file_hash = parsed_hash.canonical();
         let block_timestamp = env::block_timestamp();
-        // Use env::log to record logs permanently to the blockchain!
-        env::log(format!("Stamping file '{}' at '{}'", file_hash, block_timestamp,).as_bytes());
-        let timestamped_file_hash = env::keccak256(format!("{}{}",file_hash,block_timestamp.to_string()).as_bytes());
-        self.records.insert(file_hash,TimestampedFile{timestamp:block_timestamp,time_stamped_file_hash:timestamped_file_hash});
+        let seq = self.index_stamp(stamper, &file_hash);
+        let mut data = StampData {
+            file_hash: file_hash.clone(),
+            algorithm: parsed_hash.algorithm().tag().to_string(),
+            timestamp: block_timestamp.into(),
+            block_height: env::block_index().into(),
+            stamper: stamper.clone(),
+            seq: seq.into(),
+            commitment: None,
+        };
+        if self.records.contains_key(&file_hash) {
+            Event::StampObserved(vec![data]).emit();
+        } else {
+            let record = new_record(&file_hash, stamper);
+            data.commitment = Some(encode_hex(&record.time_stamped_file_hash));
+            self.records.insert(&file_hash, &VersionedTimestampedFile::V2(record));
+            Event::StampCreated(vec![data]).emit();
+            if self.certificates_enabled {
+                self.mint_certificate(&file_hash, stamper);
+            }
+        }
+        self.add_observation(
+            &file_hash,
+            &StampObservation {
+                stamper: Some(stamper.clone()),
+                timestamp: block_timestamp,
+                block_height: Some(env::block_index()),
+                seq: Some(seq),
+                legacy_file_hash: None,
+            },
+        );
     }
 
-    pub fn get_stamp(&self, file_hash: String) -> TimestampedFile {
-        match self.records.get(&file_hash) {
-            Some(stamp) => stamp.clone(),
-            None => TimestampedFile::default(),
+    /// Stamps the Merkle root of `file_hashes` for `stamper` and returns it, see `stamp_batch`.
+    fn stamp_batch_root(&mut self, file_hashes: &[String], stamper: &AccountId) -> Vec<u8> {
+        assert!(
+            file_hashes.len() <= MAX_BATCH_SIZE,
+            "Batch has {} file hashes, at most {} are accepted",
+            file_hashes.len(),
+            MAX_BATCH_SIZE
+        );
+        let leaves: Vec<_> = file_hashes
+            .iter()
+            .map(|file_hash| merkle::leaf_hash(parse_file_hash(file_hash).canonical().as_bytes()))
+            .collect();
+        let root = merkle::merkle_root(&leaves).unwrap_or_else(|err| env::panic(err.to_string().as_bytes()));
+        self.insert_batch_root(&root, leaves.len() as u64, stamper);
+        root
+    }
+
+    fn insert_batch_root(&mut self, root: &[u8], leaf_count: u64, stamper: &AccountId) {
+        let key = batch_root_key(root);
+        if let Some(existing) = self.batch_roots.get(&key) {
+            env::panic(format!("Merkle root '{}' is already stamped at '{}'", key, existing.record.timestamp).as_bytes());
         }
+        let record = new_record(&key, stamper);
+        let seq = self.index_stamp(stamper, &key);
+        Event::BatchRootStamped(vec![BatchRootData {
+            root: encode_hex(root),
+            leaf_count: leaf_count.into(),
+            timestamp: record.timestamp.into(),
+            block_height: env::block_index().into(),
+            stamper: stamper.clone(),
+            seq: seq.into(),
+            commitment: encode_hex(&record.time_stamped_file_hash),
+        }])
+        .emit();
+        self.batch_roots.insert(&key, &BatchRoot { record, leaf_count });
+    }
+
+    fn batch_root_view(&self, root: &[u8]) -> Option<BatchRootView> {
+        let key = batch_root_key(root);
+        self.batch_roots.get(&key).map(|batch_root| BatchRootView {
+            root: encode_hex(root),
+            leaf_count: batch_root.leaf_count.into(),
+            stamp: TimestampedFileView::new(key, batch_root.record, StampStatus::Active),
+        })
+    }
+
+    /// Adds a stamp of `file_hash`, or of a batch root key, to the stamp log and its tree, and to
+    /// the account and time indexes, and returns its sequence number in the log.
+    fn index_stamp(&mut self, stamper: &AccountId, file_hash: &str) -> u64 {
+        let seq = self.append_to_log(stamper, file_hash);
+        self.append_to_tree(seq, stamper, file_hash);
+        self.index_account_stamp(stamper, seq);
+        self.index_time_stamp(seq);
+        seq
     }
+
+    fn add_observation(&mut self, file_hash: &String, observation: &StampObservation) {
+        let mut observations = self.history.get(file_hash).unwrap_or_else(|| {
+            let mut prefix = OBSERVATIONS_PREFIX.to_vec();
+            prefix.extend(env::sha256(file_hash.as_bytes()));
+            Vector::new(prefix)
+        });
+        observations.push(observation);
+        self.history.insert(file_hash, &observations);
+    }
+}
+
+/// Record of `key`, a canonical file hash or a batch root key, stamped by `stamper` in the
+/// current block.
+fn new_record(key: &str, stamper: &AccountId) -> TimestampedFile {
+    let block_timestamp = env::block_timestamp();
+    TimestampedFile {
+        timestamp: block_timestamp,
+        time_stamped_file_hash: compute_commitment(COMMITMENT_VERSION, key, block_timestamp),
+        commitment_version: COMMITMENT_VERSION,
+        stamper: Some(stamper.clone()),
+        signer: Some(env::signer_account_id()),
+        block_height: Some(env::block_index()),
+        epoch_height: Some(env::epoch_height()),
+    }
+}
+
+/// Key under which a Merkle root is stored and committed to.
+fn batch_root_key(root: &[u8]) -> String {
+    format!("merkle-root:{}", encode_hex(root))
+}
+
+fn compute_commitment(version: u8, file_hash: &str, timestamp: u64) -> Vec<u8> {
+    env::keccak256(&commitment::commitment_preimage(version, file_hash, timestamp).unwrap())
+}
+
+fn parse_file_hash(file_hash: &str) -> FileHash {
+    FileHash::parse(file_hash).unwrap_or_else(|err| env::panic(err.to_string().as_bytes()))
 }
 
 /*
@@ -61,11 +674,19 @@ impl ProofOfTimestamp {
  * yarn test
  *
  */
+#[cfg(test)]
+#[macro_use]
+mod test_utils;
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
+    use near_sdk::VMContext;
+    use std::convert::TryInto;
+
+    const NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+    /// Deposit attached to calls by default, enough for the storage of any test stamp
+    const STAMP_DEPOSIT: Balance = NEAR;
 
     // mock the context for testing, notice "signer_account_id" that was accessed above from env::
     fn get_context(input: Vec<u8>, is_view: bool, block_timestamp:u64) -> VMContext {
@@ -77,10 +698,10 @@ mod tests {
             input,
             block_index: 0,
             block_timestamp,
-            account_balance: 0,
+            account_balance: 1000 * STAMP_DEPOSIT,
             account_locked_balance: 0,
             storage_usage: 0,
-            attached_deposit: 0,
+            attached_deposit: if is_view { 0 } else { STAMP_DEPOSIT },
             prepaid_gas: 10u64.pow(18),
             random_seed: vec![0, 1, 2],
             is_view,
@@ -89,31 +710,1514 @@ mod tests {
         }
     }
 
+    /// Contract owned by "alice_near", the contract account
+    fn new_contract() -> ProofOfTimestamp {
+        { use std::convert::TryInto; ProofOfTimestamp::new("alice_near".try_into().unwrap()) }
+    }
+
+    fn sample_hash(n: u64) -> String {
+        format!("sha256:{:064x}", n)
+    }
+
     #[test]
     fn stamp_then_get() {
         let block_timestamp = 100;
-        let file_hash = "sample file hash".to_string();
+        let file_hash = sample_hash(1);
         let context = get_context(vec![], false,block_timestamp);
         testing_env!(context);
-        let mut contract = ProofOfTimestamp::default();
+        let mut contract = new_contract();
         contract.stamp(file_hash.clone());
-        let timestamped_file_hash = env::keccak256(format!("{}{}",file_hash,block_timestamp.to_string()).as_bytes());
-        let expected_result = TimestampedFile{timestamp:block_timestamp,time_stamped_file_hash:timestamped_file_hash};
+        let mut preimage = vec![0, 0, 0, 34];
+        preimage.extend(b"near-proof-of-timestamp/commitment");
+        preimage.push(1);
+        preimage.extend(&[0, 0, 0, 71]);
+        preimage.extend(file_hash.as_bytes());
+        preimage.extend(&[0, 0, 0, 0, 0, 0, 0, 100]);
+        let expected_result = TimestampedFile {
+            timestamp: block_timestamp,
+            time_stamped_file_hash: env::keccak256(&preimage),
+            commitment_version: 1,
+            stamper: Some("carol_near".to_string()),
+            signer: Some("bob_near".to_string()),
+            block_height: Some(0),
+            epoch_height: Some(19),
+        };
+        assert_eq!(
+            Some(expected_result.clone()),
+            contract.get_stamp_borsh(file_hash.clone())
+        );
+        assert!(contract.is_stamped(file_hash.clone()));
+
+        // Re-stamping later, from the same or another account, must not move the timestamp
+        for predecessor in ["carol_near", "dave_near"].iter() {
+            let mut context = get_context(vec![], false, block_timestamp + 50);
+            context.predecessor_account_id = predecessor.to_string();
+            testing_env!(context);
+            contract.stamp(file_hash.clone());
+            assert_eq!(Some(expected_result.clone()), contract.get_stamp_borsh(file_hash.clone()));
+        }
+        assert_eq!(3, contract.get_stamp_history(file_hash.clone(), 0, 10).len());
+
+        // Other hashes are still stamped at the current block
+        contract.stamp(sample_hash(2));
+        assert_eq!(150, contract.get_stamp(sample_hash(2)).unwrap().timestamp.0);
+    }
+
+    #[test]
+    fn get_stamp_json_view() {
+        let mut context = get_context(vec![], false, 1_612_345_278_901_234_567);
+        context.block_index = 9_007_199_254_740_993;
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        let record = contract.get_stamp_borsh(sample_hash(1)).unwrap();
+        let json = near_sdk::serde_json::to_value(contract.get_stamp(sample_hash(1))).unwrap();
         assert_eq!(
-            expected_result,
-            contract.get_stamp(file_hash)
+            near_sdk::serde_json::json!({
+                "file_hash": sample_hash(1),
+                "timestamp": "1612345278901234567",
+                "timestamp_rfc3339": "2021-02-03T09:41:18.901234567Z",
+                "commitment": file_hash::encode_hex(&record.time_stamped_file_hash),
+                "commitment_version": 1,
+                "stamper": "carol_near",
+                "signer": "bob_near",
+                "block_height": "9007199254740993",
+                "epoch_height": "19",
+                "status": { "state": "active" },
+            }),
+            json
         );
     }
 
     #[test]
-    fn get_default_stamp() {
+    fn get_missing_stamp() {
         let block_timestamp = 100;
         let context = get_context(vec![], true,block_timestamp);
         testing_env!(context);
-        let contract = ProofOfTimestamp::default();
+        let contract = new_contract();
+        assert_eq!(
+            None,
+            contract.get_stamp(sample_hash(1))
+        );
+        assert!(!contract.is_stamped(sample_hash(1)));
+        assert_eq!("null", near_sdk::serde_json::to_string(&contract.get_stamp(sample_hash(1))).unwrap());
+    }
+
+    /// Migrates `legacy` and moves all of its records, owned by `owner_near`.
+    fn migrate_legacy(legacy: &LegacyProofOfTimestamp) -> ProofOfTimestamp {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context.clone());
+        env::state_write(legacy);
+        let mut contract = ProofOfTimestamp::migrate("owner_near".try_into().unwrap());
+        context.predecessor_account_id = "owner_near".to_string();
+        testing_env!(context);
+        while contract.migrate_batch(MAX_MIGRATION_BATCH) > 0 {}
+        contract
+    }
+
+    #[test]
+    fn migrate_moves_legacy_records() {
+        let mut legacy = LegacyProofOfTimestamp { records: HashMap::new() };
+        let upper_case_hash = sample_hash(0xab).to_uppercase();
+        for (i, file_hash) in ["first hash", &upper_case_hash, &sample_hash(0xab)].iter().enumerate() {
+            legacy.records.insert(
+                file_hash.to_string(),
+                TimestampedFileV1 { timestamp: i as u64 + 1, time_stamped_file_hash: vec![i as u8; 32] },
+            );
+        }
+        let contract = migrate_legacy(&legacy);
+        assert_eq!("owner_near", contract.get_owner());
+        // Both spellings of the same hash are merged, the earliest wins
+        assert_eq!(2, contract.get_stamp_history(sample_hash(0xab), 0, 10).len());
+        legacy.records.remove(&sample_hash(0xab));
+        for (file_hash, stamp) in legacy.records.iter() {
+            let record = contract.get_stamp_borsh(file_hash.clone()).unwrap();
+            assert_eq!(stamp.timestamp, record.timestamp);
+            assert_eq!(stamp.time_stamped_file_hash, record.time_stamped_file_hash);
+            assert_eq!((None, None, None, None), (record.stamper, record.signer, record.block_height, record.epoch_height));
+            let legacy_file_hash = Some(file_hash.clone()).filter(|file_hash| *file_hash == upper_case_hash);
+            assert_eq!(
+                Some(StampObservation { stamper: None, timestamp: stamp.timestamp, block_height: None, seq: None, legacy_file_hash }),
+                contract.get_first_stamp(file_hash.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn migrated_commitments_verify_offline() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut legacy = LegacyProofOfTimestamp { records: HashMap::new() };
+        let (upper_case_hash, upper_case_tag_hash) = (sample_hash(0xab).to_uppercase(), format!("SHA256:{:064x}", 0xab));
+        for (timestamp, file_hash) in [(1u64, &upper_case_hash), (2, &upper_case_tag_hash), (3, &"unparsed hash".to_string())].iter() {
+            let commitment = compute_commitment(commitment::LEGACY_COMMITMENT_VERSION, file_hash, *timestamp);
+            legacy.records.insert(file_hash.to_string(), TimestampedFileV1 { timestamp: *timestamp, time_stamped_file_hash: commitment });
+        }
+        let contract = migrate_legacy(&legacy);
+
+        let stamp = contract.get_stamp(sample_hash(0xab)).unwrap();
+        assert_eq!((sample_hash(0xab), Some(upper_case_hash)), (stamp.file_hash.clone(), stamp.legacy_file_hash.clone()));
+        assert_eq!(Ok(()), verifier::verify_commitment(&stamp));
+        let stamp = contract.get_stamp("unparsed hash".to_string()).unwrap();
+        assert_eq!(None, stamp.legacy_file_hash);
+        assert_eq!(Ok(()), verifier::verify_commitment(&stamp));
+        // The merged spelling keeps its preimage in the history
+        let merged = contract.get_stamp_history(sample_hash(0xab), 1, 1).remove(0);
+        assert_eq!(Some(upper_case_tag_hash.clone()), merged.legacy_file_hash);
         assert_eq!(
-            TimestampedFile::default(),
-            contract.get_stamp("howdy".to_string())
+            legacy.records[&upper_case_tag_hash].time_stamped_file_hash,
+            compute_commitment(commitment::LEGACY_COMMITMENT_VERSION, &upper_case_tag_hash, merged.timestamp)
         );
     }
+
+    #[test]
+    fn migration_takes_several_batches() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context.clone());
+        let records = (0..150).map(|n| (sample_hash(n), TimestampedFileV1 { timestamp: n + 1, time_stamped_file_hash: vec![0; 32] }));
+        env::state_write(&LegacyProofOfTimestamp { records: records.collect() });
+        let mut contract = ProofOfTimestamp::migrate("owner_near".try_into().unwrap());
+        assert_eq!(150, contract.count_unmigrated_records());
+        assert!(!contract.is_stamped(sample_hash(0)));
+
+        context.predecessor_account_id = "owner_near".to_string();
+        testing_env!(context.clone());
+        assert_eq!(50, contract.migrate_batch(u64::MAX));
+        assert!(contract.is_stamped(sample_hash(99)) && !contract.is_stamped(sample_hash(100)));
+        // No stamp can take the place of a legacy one before they are all moved
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.stamp(sample_hash(149))));
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("Stamping is blocked until the legacy records are migrated, 50 are left"), "{}", message);
+
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        assert_eq!(0, contract.migrate_batch(u64::MAX));
+        assert_eq!(vec!["Migrated 50 legacy records, 0 left".to_string()], test_utils::get_logs());
+        assert!(!env::storage_has_key(LEGACY_RECORDS_KEY));
+        assert_eq!(Some(150), contract.get_stamp(sample_hash(149)).map(|stamp| stamp.timestamp.0));
+        assert_eq!(0, contract.migrate_batch(1));
+        contract.stamp(sample_hash(150));
+        assert!(contract.is_stamped(sample_hash(150)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_migrates_batches() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = context.current_account_id.clone();
+        testing_env!(context.clone());
+        env::state_write(&LegacyProofOfTimestamp { records: HashMap::new() });
+        let mut contract = ProofOfTimestamp::migrate("owner_near".try_into().unwrap());
+        context.predecessor_account_id = "carol_near".to_string();
+        testing_env!(context);
+        contract.migrate_batch(10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the contract account can migrate its state")]
+    fn migrate_requires_contract_account() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        env::state_write(&LegacyProofOfTimestamp { records: HashMap::new() });
+        ProofOfTimestamp::migrate("owner_near".try_into().unwrap());
+    }
+
+    #[test]
+    fn stamp_history_keeps_every_stamper() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        let file_hash = sample_hash(1);
+        for (i, stamper) in ["author_near", "reviewer_near", "legal_near"].iter().enumerate() {
+            let mut context = get_context(vec![], false, 100 + i as u64);
+            context.predecessor_account_id = stamper.to_string();
+            context.block_index = 10 + i as u64;
+            testing_env!(context);
+            contract.stamp(file_hash.clone());
+        }
+        let observation = |stamper: &str, i: u64| StampObservation {
+            stamper: Some(stamper.to_string()),
+            timestamp: 100 + i,
+            block_height: Some(10 + i),
+            seq: Some(i),
+            legacy_file_hash: None,
+        };
+        assert_eq!(Some(observation("author_near", 0)), contract.get_first_stamp(file_hash.clone()));
+        assert_eq!(
+            vec![observation("reviewer_near", 1), observation("legal_near", 2)],
+            contract.get_stamp_history(file_hash.clone(), 1, 5)
+        );
+        assert_eq!(vec![observation("author_near", 0)], contract.get_stamp_history(file_hash.clone(), 0, 1));
+        assert!(contract.get_stamp_history(file_hash, 3, 5).is_empty());
+        assert_eq!(None, contract.get_first_stamp(sample_hash(2)));
+        assert!(contract.get_stamp_history(sample_hash(2), 0, 5).is_empty());
+    }
+
+    #[test]
+    fn stamp_normalizes_file_hash() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        let digest = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        contract.stamp(format!("SHA256:{}", digest));
+        contract.stamp(format!("multihash:1220{}", digest));
+        let canonical = format!("sha256:{}", digest.to_lowercase());
+        assert_eq!(100, contract.get_stamp(canonical.clone()).unwrap().timestamp.0);
+        assert_eq!(2, contract.get_stamp_history(canonical, 0, 10).len());
+    }
+
+    #[test]
+    #[should_panic(expected = "File hash must be tagged with its algorithm")]
+    fn stamp_rejects_untagged_hash() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp("sample file hash".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "A keccak256 digest is 32 bytes long, got 20 bytes")]
+    fn stamp_rejects_truncated_digest() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp(format!("keccak256:{}", "ab".repeat(20)));
+    }
+
+    #[test]
+    fn commitment_test_vectors() {
+        use near_sdk::serde_json::Value;
+        testing_env!(get_context(vec![], true, 0));
+        let vectors: Value = near_sdk::serde_json::from_str(include_str!("../test-vectors/commitment.json")).unwrap();
+        assert_eq!(Value::from(file_hash::encode_hex(commitment::COMMITMENT_DOMAIN)), vectors["domain_hex"]);
+        for vector in vectors["vectors"].as_array().unwrap() {
+            let version = vector["version"].as_u64().unwrap() as u8;
+            let file_hash = vector["file_hash"].as_str().unwrap();
+            let timestamp = vector["timestamp"].as_str().unwrap().parse().unwrap();
+            let preimage = commitment::commitment_preimage(version, file_hash, timestamp).unwrap();
+            assert_eq!(vector["preimage_hex"].as_str().unwrap(), file_hash::encode_hex(&preimage));
+            assert_eq!(
+                vector["commitment_hex"].as_str().unwrap(),
+                file_hash::encode_hex(&compute_commitment(version, file_hash, timestamp))
+            );
+        }
+    }
+
+    #[test]
+    fn commitment_is_unambiguous() {
+        testing_env!(get_context(vec![], true, 0));
+        // Under the legacy scheme "...a1" at 23 and "...a" at 123 hash the same string
+        let (short, long) = ("sha256:a".to_string(), "sha256:a1".to_string());
+        assert_eq!(compute_commitment(0, &long, 23), compute_commitment(0, &short, 123));
+        assert_ne!(compute_commitment(1, &long, 23), compute_commitment(1, &short, 123));
+        assert_eq!(None, commitment::commitment_preimage(2, &short, 123));
+    }
+
+    #[test]
+    fn stamp_batch_then_prove_inclusion() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        let file_hashes: Vec<_> = (0..5).map(sample_hash).collect();
+        let root = contract.stamp_batch(file_hashes.clone());
+        let leaves: Vec<_> = file_hashes.iter().map(|file_hash| merkle::leaf_hash(file_hash.as_bytes())).collect();
+        assert_eq!(encode_hex(&merkle::merkle_root(&leaves).unwrap()), root);
+
+        let proof = merkle::merkle_proof(&leaves, 3).unwrap();
+        let batched = contract.get_batched_stamp(sample_hash(3), proof.clone()).unwrap();
+        assert_eq!(root, batched.root);
+        assert_eq!(5, batched.leaf_count.0);
+        assert_eq!(100, batched.stamp.timestamp.0);
+        assert_eq!(format!("merkle-root:{}", root), batched.stamp.file_hash);
+        assert_eq!(Some(batched), contract.get_batch_root(root));
+        // The proof of another leaf resolves to an unknown root
+        assert_eq!(None, contract.get_batched_stamp(sample_hash(4), proof));
+        // Batched file hashes are not stamped individually
+        assert!(!contract.is_stamped(sample_hash(3)));
+    }
+
+    #[test]
+    fn stamp_merkle_root_computed_off_chain() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        let leaves: Vec<_> = (0..1000).map(|i| merkle::leaf_hash(sample_hash(i).as_bytes())).collect();
+        let root = encode_hex(&merkle::merkle_root(&leaves).unwrap());
+        contract.stamp_merkle_root(root.to_uppercase(), 1000);
+        let proof = merkle::merkle_proof(&leaves, 999).unwrap();
+        assert_eq!(root, contract.get_batched_stamp(sample_hash(999), proof).unwrap().root);
+    }
+
+    #[test]
+    #[should_panic(expected = "is already stamped at '100'")]
+    fn stamp_merkle_root_twice() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        let root = contract.stamp_batch(vec![sample_hash(1), sample_hash(2)]);
+        contract.stamp_merkle_root(root, 2);
+    }
+
+    #[test]
+    fn verify_inclusion_in_odd_sized_trees() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        for size in [1u64, 3, 7].iter() {
+            let file_hashes: Vec<_> = (0..*size).map(|i| sample_hash(size * 100 + i)).collect();
+            let root = contract.stamp_batch(file_hashes.clone());
+            let leaves: Vec<_> = file_hashes.iter().map(|file_hash| merkle::leaf_hash(file_hash.as_bytes())).collect();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle::merkle_proof(&leaves, index).unwrap();
+                let stamp = contract.verify_inclusion(encode_hex(leaf), proof.siblings, proof.sides).unwrap();
+                assert_eq!(format!("merkle-root:{}", root), stamp.file_hash);
+            }
+        }
+        // The last leaf of 7 has no sibling on the first level, flipping a side misses the root
+        let leaves: Vec<_> = (0..7).map(|i| merkle::leaf_hash(sample_hash(700 + i).as_bytes())).collect();
+        let mut proof = merkle::merkle_proof(&leaves, 6).unwrap();
+        assert_eq!(vec![Side::Left, Side::Left], proof.sides);
+        proof.sides[0] = Side::Right;
+        assert_eq!(None, contract.verify_inclusion(encode_hex(&leaves[6]), proof.siblings, proof.sides));
+    }
+
+    #[test]
+    fn verify_inclusion_rejects_malformed_proofs() {
+        let context = get_context(vec![], true, 100);
+        testing_env!(context);
+        let contract = new_contract();
+        let leaf = "ab".repeat(32);
+        let cases = vec![
+            ("ab".repeat(31), vec![leaf.clone()], vec![Side::Left], "Leaf hash must be a hex encoded 32 byte hash"),
+            ("zz".repeat(32), vec![leaf.clone()], vec![Side::Left], "Leaf hash must be a hex encoded 32 byte hash"),
+            (leaf.clone(), vec![leaf.clone()], vec![], "Proof has 1 siblings but 0 sides"),
+            (leaf.clone(), vec!["ab".repeat(33)], vec![Side::Right], "Proof sibling 0 is not a hex encoded 32 byte hash"),
+            (leaf.clone(), vec![leaf.clone(), "xyz".to_string()], vec![Side::Right; 2], "Proof sibling 1 is not a hex encoded 32 byte hash"),
+        ];
+        for (leaf_hash, siblings, sides, expected) in cases {
+            let result = std::panic::catch_unwind(|| contract.verify_inclusion(leaf_hash, siblings, sides));
+            let message = result.unwrap_err().downcast::<String>().unwrap();
+            assert!(message.contains(expected), "{}", message);
+        }
+        // A well formed proof to an unknown root is not an error
+        assert_eq!(None, contract.verify_inclusion(leaf.clone(), vec![leaf], vec![Side::Left]));
+    }
+
+    #[test]
+    fn stamps_emit_events() {
+        let mut context = get_context(vec![], false, 100);
+        context.block_index = 7;
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1).to_uppercase());
+        contract.stamp(sample_hash(1));
+        let root = contract.stamp_batch(vec![sample_hash(2), sample_hash(3)]);
+        let commitment = |key: &str| encode_hex(&compute_commitment(COMMITMENT_VERSION, key, 100));
+        assert_eq!(
+            vec![
+                near_sdk::serde_json::json!({
+                    "standard": "proof_of_timestamp",
+                    "version": "1.0.0",
+                    "event": "stamp_created",
+                    "data": [{
+                        "file_hash": sample_hash(1),
+                        "algorithm": "sha256",
+                        "timestamp": "100",
+                        "block_height": "7",
+                        "stamper": "carol_near",
+                        "seq": "0",
+                        "commitment": commitment(&sample_hash(1)),
+                    }],
+                }),
+                near_sdk::serde_json::json!({
+                    "standard": "proof_of_timestamp",
+                    "version": "1.0.0",
+                    "event": "stamp_observed",
+                    "data": [{
+                        "file_hash": sample_hash(1),
+                        "algorithm": "sha256",
+                        "timestamp": "100",
+                        "block_height": "7",
+                        "stamper": "carol_near",
+                        "seq": "1",
+                    }],
+                }),
+                near_sdk::serde_json::json!({
+                    "standard": "proof_of_timestamp",
+                    "version": "1.0.0",
+                    "event": "batch_root_stamped",
+                    "data": [{
+                        "root": root,
+                        "leaf_count": "2",
+                        "timestamp": "100",
+                        "block_height": "7",
+                        "stamper": "carol_near",
+                        "seq": "2",
+                        "commitment": commitment(&format!("merkle-root:{}", root)),
+                    }],
+                }),
+            ],
+            test_utils::get_events()
+        );
+    }
+
+    #[derive(near_sdk::serde::Deserialize)]
+    #[serde(crate = "near_sdk::serde")]
+    enum Action {
+        Transfer { deposit: Balance },
+        FunctionCall { method_name: String },
+    }
+
+    /// Actions of the receipts created by the last call, with their receiver
+    fn receipt_actions() -> Vec<(AccountId, Action)> {
+        #[derive(near_sdk::serde::Deserialize)]
+        #[serde(crate = "near_sdk::serde")]
+        struct Receipt {
+            receiver_id: AccountId,
+            actions: Vec<Action>,
+        }
+        let receipts = near_sdk::serde_json::to_string(&env::created_receipts()).unwrap();
+        let receipts: Vec<Receipt> = near_sdk::serde_json::from_str(&receipts).unwrap();
+        receipts
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver_id = receipt.receiver_id;
+                receipt.actions.into_iter().map(move |action| (receiver_id.clone(), action))
+            })
+            .collect()
+    }
+
+    /// Transfers created by the last call, as (receiver, amount)
+    fn transfers() -> Vec<(AccountId, Balance)> {
+        receipt_actions()
+            .into_iter()
+            .filter_map(|(receiver_id, action)| match action {
+                Action::Transfer { deposit } => Some((receiver_id, deposit)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Function calls created by the last call, as (receiver, method)
+    fn function_calls() -> Vec<(AccountId, String)> {
+        receipt_actions()
+            .into_iter()
+            .filter_map(|(receiver_id, action)| match action {
+                Action::FunctionCall { method_name } => Some((receiver_id, method_name)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stamp_charges_storage_and_refunds_excess() {
+        let mut context = get_context(vec![], false, 100);
+        context.storage_usage = 1000;
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        let cost = Balance::from(env::storage_usage() - 1000) * storage::STORAGE_PRICE_PER_BYTE;
+        assert!(cost > 0);
+        assert_eq!(vec![("carol_near".to_string(), STAMP_DEPOSIT - cost)], transfers());
+    }
+
+    #[test]
+    fn stamp_with_exact_deposit_refunds_nothing() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        // The first stamp of an account also creates its index
+        contract.stamp(sample_hash(1));
+        contract.stamp(sample_hash(2));
+        testing_env!(context.clone());
+        contract.stamp(sample_hash(3));
+        let cost = STAMP_DEPOSIT - transfers()[0].1;
+        contract.stamp(sample_hash(4));
+        // Stamping another hash of the same length costs exactly the same, as long as it stores
+        // as many transparency log nodes, one for every stamp of even sequence number
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = cost;
+        testing_env!(context);
+        contract.stamp(sample_hash(5));
+        assert!(transfers().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "yoctoNEAR to cover")]
+    fn stamp_without_deposit_fails() {
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = 0;
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "yoctoNEAR to cover")]
+    fn stamp_batch_with_insufficient_deposit_fails() {
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = storage::STORAGE_PRICE_PER_BYTE;
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.stamp_batch(vec![sample_hash(1), sample_hash(2)]);
+    }
+
+    #[test]
+    fn prepaid_storage_is_charged_per_stamp() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        let carol = || "carol_near".to_string().try_into().unwrap();
+        let min = storage::storage_balance_min();
+        assert_eq!(min, contract.storage_balance_bounds().min.0);
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!((STAMP_DEPOSIT, STAMP_DEPOSIT - min), (balance.total.0, balance.available.0));
+
+        // Keep the storage usage of the state written so far across contexts
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 0;
+        testing_env!(context.clone());
+        let initial_storage_usage = env::storage_usage();
+        contract.stamp(sample_hash(1));
+        let cost = Balance::from(env::storage_usage() - initial_storage_usage) * storage::STORAGE_PRICE_PER_BYTE;
+        assert!(transfers().is_empty());
+        let balance = contract.storage_balance_of(carol()).unwrap();
+        assert_eq!((STAMP_DEPOSIT, STAMP_DEPOSIT - min - cost), (balance.total.0, balance.available.0));
+
+        // Only the available balance can be withdrawn, and stamps keep the account registered
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        let balance = contract.storage_withdraw(None);
+        assert_eq!((min + cost, 0), (balance.total.0, balance.available.0));
+        assert_eq!(vec![("carol_near".to_string(), STAMP_DEPOSIT - min - cost)], transfers());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.storage_unregister(None)));
+        assert!(result.is_err());
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(vec![("carol_near".to_string(), min)], transfers());
+        assert_eq!(None, contract.storage_balance_of(carol()));
+        assert!(!contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "can't cover")]
+    fn stamp_fails_when_prepaid_storage_runs_out() {
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = storage::storage_balance_min();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.storage_deposit(None, Some(true));
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.stamp(sample_hash(1));
+    }
+
+    #[test]
+    fn storage_deposit_for_another_account() {
+        let context = get_context(vec![], false, 100);
+        testing_env!(context);
+        let mut contract = new_contract();
+        let dave: ValidAccountId = "dave_near".to_string().try_into().unwrap();
+        let balance = contract.storage_deposit(Some(dave.clone()), Some(true));
+        assert_eq!(storage::storage_balance_min(), balance.total.0);
+        assert_eq!(vec![("carol_near".to_string(), STAMP_DEPOSIT - storage::storage_balance_min())], transfers());
+        assert_eq!(Some(balance), contract.storage_balance_of(dave));
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract should be initialized before usage")]
+    fn uninitialized_contract_panics() {
+        testing_env!(get_context(vec![], true, 100));
+        ProofOfTimestamp::default();
+    }
+
+    #[test]
+    fn owner_pauses_stamping() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        contract.pause();
+        assert!(contract.is_paused());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.stamp(sample_hash(2))));
+        assert!(result.is_err());
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        // Views keep working
+        assert!(contract.is_stamped(sample_hash(1)));
+        assert!(!contract.is_stamped(sample_hash(2)));
+        contract.unpause();
+        contract.stamp(sample_hash(2));
+        assert!(contract.is_stamped(sample_hash(2)));
+        assert_eq!(
+            near_sdk::serde_json::json!({
+                "standard": "proof_of_timestamp",
+                "version": "1.0.0",
+                "event": "unpaused",
+                "data": [{ "by": "alice_near" }],
+            }),
+            test_utils::get_events()[0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Stamping is paused")]
+    fn stamp_batch_while_paused_fails() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.pause();
+        contract.stamp_batch(vec![sample_hash(1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_owner_pauses() {
+        testing_env!(get_context(vec![], false, 100));
+        new_contract().pause();
+    }
+
+    #[test]
+    fn ownership_transfer_takes_two_steps() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.pause();
+        contract.propose_owner(None);
+        contract.propose_owner(Some("dave_near".try_into().unwrap()));
+        assert_eq!(("alice_near".to_string(), Some("dave_near".to_string())), (contract.get_owner(), contract.get_proposed_owner()));
+        let events = test_utils::get_events();
+        assert_eq!(
+            vec!["paused", "ownership_proposed", "ownership_proposed"],
+            events.iter().map(|event| event["event"].as_str().unwrap()).collect::<Vec<_>>()
+        );
+        assert_eq!(near_sdk::serde_json::json!([{ "by": "alice_near" }]), events[0]["data"]);
+        assert_eq!(near_sdk::serde_json::json!([{ "owner_id": "alice_near", "proposed_owner_id": null }]), events[1]["data"]);
+        assert_eq!(near_sdk::serde_json::json!([{ "owner_id": "alice_near", "proposed_owner_id": "dave_near" }]), events[2]["data"]);
+
+        context.predecessor_account_id = "carol_near".to_string();
+        testing_env!(context.clone());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.accept_owner()));
+        assert!(result.is_err());
+
+        context.predecessor_account_id = "dave_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.accept_owner();
+        assert_eq!(("dave_near".to_string(), None), (contract.get_owner(), contract.get_proposed_owner()));
+        assert_eq!(
+            near_sdk::serde_json::json!([{ "old_owner_id": "alice_near", "new_owner_id": "dave_near" }]),
+            test_utils::get_events()[0]["data"]
+        );
+        assert_eq!("ownership_transferred", test_utils::get_events()[0]["event"]);
+        contract.unpause();
+        assert!(!contract.is_paused());
+    }
+
+    #[test]
+    fn roles_are_granted_revoked_and_listed() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        let account = |name: &str| -> ValidAccountId { name.to_string().try_into().unwrap() };
+        assert!(contract.grant_role(Role::Admin, account("admin_near")));
+        assert!(!contract.grant_role(Role::Admin, account("admin_near")));
+
+        // Admins manage the other roles
+        context.predecessor_account_id = "admin_near".to_string();
+        testing_env!(context.clone());
+        for name in ["notary1_near", "notary2_near", "notary3_near"].iter() {
+            assert!(contract.grant_role(Role::Notary, account(name)));
+        }
+        assert!(contract.grant_role(Role::Auditor, account("notary1_near")));
+        assert!(contract.revoke_role(Role::Notary, account("notary1_near")));
+        assert!(!contract.revoke_role(Role::Notary, account("notary1_near")));
+
+        assert_eq!(vec![Role::Auditor], contract.get_roles(account("notary1_near")));
+        assert_eq!(vec![Role::Admin], contract.get_roles(account("admin_near")));
+        assert!(contract.get_roles(account("alice_near")).is_empty());
+        assert!(contract.has_role(Role::Notary, account("notary2_near")));
+        assert_eq!(2, contract.get_role_member_count(Role::Notary));
+        assert_eq!(vec!["notary3_near".to_string(), "notary2_near".to_string()], contract.get_role_members(Role::Notary, 0, 10));
+        assert_eq!(vec!["notary2_near".to_string()], contract.get_role_members(Role::Notary, 1, 10));
+        assert!(contract.get_role_members(Role::Notary, 2, 10).is_empty());
+
+        // Events of the calls made by the admin
+        let events = test_utils::get_events();
+        let names: Vec<_> = events.iter().map(|event| event["event"].as_str().unwrap()).collect();
+        assert_eq!(vec!["role_granted", "role_granted", "role_granted", "role_granted", "role_revoked"], names);
+        assert_eq!(
+            near_sdk::serde_json::json!([{ "role": "notary", "account_id": "notary1_near", "by": "admin_near" }]),
+            events[4]["data"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can manage admins")]
+    fn admins_cant_grant_admin() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.grant_role(Role::Admin, "admin_near".try_into().unwrap());
+        context.predecessor_account_id = "admin_near".to_string();
+        testing_env!(context);
+        contract.grant_role(Role::Admin, "dave_near".try_into().unwrap());
+    }
+
+    #[test]
+    fn permissioned_mode_only_lets_notaries_stamp() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.grant_role(Role::Notary, "carol_near".try_into().unwrap());
+        contract.set_permissioned(true);
+        assert!(contract.is_permissioned());
+
+        context.predecessor_account_id = "carol_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.stamp(sample_hash(1));
+        assert!(contract.is_stamped(sample_hash(1)));
+
+        context.predecessor_account_id = "dave_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.stamp(sample_hash(2))));
+        assert!(result.is_err());
+
+        // Back to open mode, anyone can stamp
+        context.predecessor_account_id = "alice_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.set_permissioned(false);
+        context.predecessor_account_id = "dave_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.stamp(sample_hash(2));
+        assert!(contract.is_stamped(sample_hash(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn admins_cannot_switch_the_stamping_mode() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.grant_role(Role::Admin, "admin_near".try_into().unwrap());
+        context.predecessor_account_id = "admin_near".to_string();
+        testing_env!(context);
+        contract.set_permissioned(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only notaries can stamp, 'carol_near' is not one")]
+    fn permissioned_mode_rejects_batches_of_other_accounts() {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.set_permissioned(true);
+        context.predecessor_account_id = "carol_near".to_string();
+        testing_env!(context);
+        contract.stamp_batch(vec![sample_hash(1)]);
+    }
+
+    fn contract_with_fees() -> ProofOfTimestamp {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context);
+        let mut contract = new_contract();
+        contract.set_fee_schedule(FeeSchedule {
+            stamp_fee: (NEAR / 10).into(),
+            batch_root_fee: (NEAR / 20).into(),
+            batch_leaf_fee: (NEAR / 100).into(),
+        });
+        contract
+    }
+
+    #[test]
+    fn fees_are_collected_and_withdrawn() {
+        let mut contract = contract_with_fees();
+        assert_eq!(NEAR / 10, contract.get_fee_schedule().stamp_fee.0);
+        assert_eq!(0, contract.get_collected_fees().0);
+
+        let mut context = get_context(vec![], false, 100);
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        let initial_storage_usage = env::storage_usage();
+        contract.stamp(sample_hash(1));
+        // The fee comes out of the deposit before storage
+        let cost = Balance::from(env::storage_usage() - initial_storage_usage) * storage::STORAGE_PRICE_PER_BYTE;
+        assert_eq!(vec![("carol_near".to_string(), NEAR - NEAR / 10 - cost)], transfers());
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.stamp_batch(vec![sample_hash(2), sample_hash(3), sample_hash(4)]);
+        let collected = NEAR / 10 + NEAR / 20 + 3 * NEAR / 100;
+        assert_eq!(collected, contract.get_collected_fees().0);
+
+        context.predecessor_account_id = "alice_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.withdraw_fees((NEAR / 10).into(), "treasury_near".try_into().unwrap());
+        assert_eq!(vec![("treasury_near".to_string(), NEAR / 10)], transfers());
+        assert_eq!(vec![("alice_near".to_string(), "on_fees_withdrawn".to_string())], function_calls());
+        assert_eq!(collected - NEAR / 10, contract.get_collected_fees().0);
+    }
+
+    #[test]
+    fn failed_fee_withdrawal_is_credited_back() {
+        let mut contract = contract_with_fees();
+        let mut context = get_context(vec![], false, 100);
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.stamp(sample_hash(1));
+
+        context.predecessor_account_id = "alice_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.withdraw_fees((NEAR / 10).into(), "treasury_near".try_into().unwrap());
+        assert_eq!(0, contract.get_collected_fees().0);
+
+        test_utils::testing_env_with_promise_results(context.clone(), vec![near_sdk::PromiseResult::Failed]);
+        contract.on_fees_withdrawn((NEAR / 10).into());
+        assert_eq!(NEAR / 10, contract.get_collected_fees().0);
+        assert_eq!(vec![format!("Withdrawal of {} yoctoNEAR failed, fees credited back", NEAR / 10)], test_utils::get_logs());
+
+        // A successful transfer leaves the treasury as it is
+        test_utils::testing_env_with_promise_results(context, vec![near_sdk::PromiseResult::Successful(vec![])]);
+        contract.on_fees_withdrawn((NEAR / 10).into());
+        assert_eq!(NEAR / 10, contract.get_collected_fees().0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Method is private")]
+    fn on_fees_withdrawn_is_private() {
+        let mut contract = contract_with_fees();
+        test_utils::testing_env_with_promise_results(get_context(vec![], false, 100), vec![near_sdk::PromiseResult::Failed]);
+        contract.on_fees_withdrawn(1.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Must attach the fee of 100000000000000000000000 yoctoNEAR, attached 0")]
+    fn prepaid_storage_does_not_pay_fees() {
+        let mut contract = contract_with_fees();
+        let mut context = get_context(vec![], false, 100);
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+        context.storage_usage = env::storage_usage();
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.stamp(sample_hash(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Must attach the fee of 50000000000000000000000 yoctoNEAR")]
+    fn stamp_merkle_root_without_fee_fails() {
+        let mut contract = contract_with_fees();
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = NEAR / 50;
+        testing_env!(context);
+        contract.stamp_merkle_root(encode_hex(&[1; 32]), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't withdraw 1 yoctoNEAR, 0 were collected")]
+    fn withdraw_fees_is_capped_by_collected_fees() {
+        let mut contract = contract_with_fees();
+        contract.withdraw_fees(1.into(), "treasury_near".try_into().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_owner_sets_fees() {
+        let mut contract = contract_with_fees();
+        testing_env!(get_context(vec![], false, 100));
+        contract.set_fee_schedule(FeeSchedule::default());
+    }
+
+    /// Contract accepting "usd_near" tokens, in which carol_near prepaid its storage. The next
+    /// calls come from the token contract
+    fn contract_accepting_tokens() -> (ProofOfTimestamp, VMContext) {
+        let mut context = get_context(vec![], false, 100);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.set_token_fee_schedule(
+            "usd_near".try_into().unwrap(),
+            FeeSchedule { stamp_fee: 10.into(), batch_root_fee: 15.into(), batch_leaf_fee: 1.into() },
+        );
+        context.predecessor_account_id = "carol_near".to_string();
+        testing_env!(context.clone());
+        contract.storage_deposit(None, None);
+        context.predecessor_account_id = "usd_near".to_string();
+        context.attached_deposit = 0;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        (contract, context)
+    }
+
+    fn ft_stamp(contract: &mut ProofOfTimestamp, amount: u128, file_hashes: Vec<String>, batch: bool) -> u128 {
+        let msg = near_sdk::serde_json::to_string(&ft_payments::FtStampMessage { file_hashes, batch }).unwrap();
+        match contract.ft_on_transfer("carol_near".try_into().unwrap(), amount.into(), msg) {
+            near_sdk::PromiseOrValue::Value(unused) => unused.0,
+            near_sdk::PromiseOrValue::Promise(_) => panic!("ft_on_transfer should return a value"),
+        }
+    }
+
+    #[test]
+    fn ft_transfer_call_stamps_and_refunds_unused_tokens() {
+        let (mut contract, mut context) = contract_accepting_tokens();
+        assert_eq!(80, ft_stamp(&mut contract, 100, vec![sample_hash(1), sample_hash(2)], false));
+        assert_eq!(Some("carol_near".to_string()), contract.get_stamp(sample_hash(2)).unwrap().stamper);
+        let file_hashes = vec![sample_hash(3), sample_hash(4), sample_hash(5)];
+        assert_eq!(82, ft_stamp(&mut contract, 100, file_hashes.clone(), true));
+        let leaves: Vec<_> = file_hashes.iter().map(|file_hash| merkle::leaf_hash(file_hash.as_bytes())).collect();
+        let proof = merkle::merkle_proof(&leaves, 0).unwrap();
+        assert_eq!(
+            Some("carol_near".to_string()),
+            contract.get_batched_stamp(sample_hash(3), proof).unwrap().stamp.stamper
+        );
+        // Storage came out of the prepaid balance
+        assert!(contract.storage_balance_of("carol_near".try_into().unwrap()).unwrap().available.0 < STAMP_DEPOSIT - storage::storage_balance_min());
+        assert_eq!(38, contract.get_collected_token_fees("usd_near".try_into().unwrap()).0);
+
+        context.predecessor_account_id = "alice_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.withdraw_token_fees("usd_near".try_into().unwrap(), 30.into(), "treasury_near".try_into().unwrap());
+        assert_eq!(
+            vec![("usd_near".to_string(), "ft_transfer".to_string()), ("alice_near".to_string(), "on_token_fees_withdrawn".to_string())],
+            function_calls()
+        );
+        assert_eq!(8, contract.get_collected_token_fees("usd_near".try_into().unwrap()).0);
+
+        // A failed transfer credits the fees back
+        test_utils::testing_env_with_promise_results(context, vec![near_sdk::PromiseResult::Failed]);
+        contract.on_token_fees_withdrawn("usd_near".to_string(), 30.into());
+        assert_eq!(38, contract.get_collected_token_fees("usd_near".try_into().unwrap()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Must transfer the fee of 20 tokens, transferred 19")]
+    fn ft_transfer_call_below_fee_fails() {
+        let (mut contract, _) = contract_accepting_tokens();
+        ft_stamp(&mut contract, 19, vec![sample_hash(1), sample_hash(2)], false);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token 'fake_near' is not accepted")]
+    fn ft_transfer_call_of_other_tokens_fails() {
+        let (mut contract, mut context) = contract_accepting_tokens();
+        context.predecessor_account_id = "fake_near".to_string();
+        testing_env!(context);
+        ft_stamp(&mut contract, 100, vec![sample_hash(1)], false);
+    }
+
+    #[test]
+    #[should_panic(expected = "'dave_near' must prepay its storage")]
+    fn ft_transfer_call_requires_prepaid_storage() {
+        let (mut contract, _) = contract_accepting_tokens();
+        let msg = near_sdk::serde_json::json!({ "file_hashes": [sample_hash(1)] }).to_string();
+        contract.ft_on_transfer("dave_near".try_into().unwrap(), 100.into(), msg);
+    }
+
+    /// Contract minting certificates, in which carol_near stamped sample_hash(1)
+    fn contract_with_certificate() -> (ProofOfTimestamp, VMContext) {
+        let mut context = get_context(vec![], false, 1_612_345_278_901_234_567);
+        context.predecessor_account_id = "alice_near".to_string();
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.set_certificates_enabled(true);
+        context.predecessor_account_id = "carol_near".to_string();
+        testing_env!(context.clone());
+        contract.stamp(sample_hash(1));
+        context.storage_usage = env::storage_usage();
+        (contract, context)
+    }
+
+    #[test]
+    fn certificates_are_minted_with_new_records() {
+        let (mut contract, mut context) = contract_with_certificate();
+        assert!(contract.certificates_enabled());
+        let record = contract.get_stamp(sample_hash(1)).unwrap();
+        let token = contract.nft_token(sample_hash(1)).unwrap();
+        assert_eq!(("carol_near", Some("1612345278901")), (token.owner_id.as_str(), token.metadata.issued_at.as_deref()));
+        assert_eq!(
+            near_sdk::serde_json::json!({
+                "file_hash": sample_hash(1),
+                "timestamp": "1612345278901234567",
+                "commitment": record.commitment,
+                "commitment_version": 1,
+            }),
+            near_sdk::serde_json::from_str::<near_sdk::serde_json::Value>(&token.metadata.extra.unwrap()).unwrap()
+        );
+        assert_eq!("nft-1.0.0", contract.nft_metadata().spec);
+        let events = test_utils::get_events();
+        assert_eq!(
+            near_sdk::serde_json::json!({
+                "standard": "nep171",
+                "version": "1.0.0",
+                "event": "nft_mint",
+                "data": [{ "owner_id": "carol_near", "token_ids": [sample_hash(1)] }],
+            }),
+            events[1]
+        );
+
+        // Stamping the hash again doesn't mint, other hashes do
+        context.predecessor_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.stamp(sample_hash(1));
+        contract.stamp(sample_hash(2));
+        assert_eq!(2, contract.nft_total_supply().0);
+        let carol: ValidAccountId = "carol_near".try_into().unwrap();
+        assert_eq!(1, contract.nft_supply_for_owner(carol.clone()).0);
+        assert_eq!(vec![sample_hash(1)], contract.nft_tokens_for_owner(carol, None, None).into_iter().map(|token| token.token_id).collect::<Vec<_>>());
+        assert_eq!("dave_near", contract.nft_token(sample_hash(2)).unwrap().owner_id);
+    }
+
+    #[test]
+    fn transferring_a_certificate_keeps_the_record() {
+        let (mut contract, mut context) = contract_with_certificate();
+        let record = contract.get_stamp(sample_hash(1));
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.nft_transfer("dave_near".try_into().unwrap(), sample_hash(1), None, Some("sold".to_string()));
+        assert_eq!("dave_near", contract.nft_token(sample_hash(1)).unwrap().owner_id);
+        assert!(contract.nft_tokens_for_owner("carol_near".try_into().unwrap(), None, None).is_empty());
+        assert_eq!(1, contract.nft_tokens_for_owner("dave_near".try_into().unwrap(), Some(0.into()), Some(10)).len());
+        assert_eq!(record, contract.get_stamp(sample_hash(1)));
+        assert_eq!(Some("carol_near".to_string()), record.unwrap().stamper);
+        assert_eq!("nft_transfer", test_utils::get_events().last().unwrap()["event"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the holder of a certificate can transfer it")]
+    fn only_the_holder_transfers_a_certificate() {
+        let (mut contract, mut context) = contract_with_certificate();
+        context.predecessor_account_id = "dave_near".to_string();
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.nft_transfer("dave_near".try_into().unwrap(), sample_hash(1), None, None);
+    }
+
+    #[test]
+    fn refused_nft_transfer_call_is_reverted() {
+        let (mut contract, mut context) = contract_with_certificate();
+        context.attached_deposit = 1;
+        testing_env!(context.clone());
+        contract.nft_transfer_call("market_near".try_into().unwrap(), sample_hash(1), None, None, String::new());
+        assert_eq!(
+            vec![("market_near".to_string(), "nft_on_transfer".to_string()), ("alice_near".to_string(), "nft_resolve_transfer".to_string())],
+            function_calls()
+        );
+        assert_eq!("market_near", contract.nft_token(sample_hash(1)).unwrap().owner_id);
+
+        // The receiver returns true to give the certificate back
+        context.predecessor_account_id = "alice_near".to_string();
+        context.attached_deposit = 0;
+        context.storage_usage = env::storage_usage();
+        test_utils::testing_env_with_promise_results(context, vec![near_sdk::PromiseResult::Successful(b"true".to_vec())]);
+        assert!(!contract.nft_resolve_transfer("carol_near".to_string(), "market_near".to_string(), sample_hash(1), None));
+        assert_eq!("carol_near", contract.nft_token(sample_hash(1)).unwrap().owner_id);
+    }
+
+    #[test]
+    fn certificates_are_off_by_default() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        assert_eq!(None, contract.nft_token(sample_hash(1)));
+        assert_eq!(0, contract.nft_total_supply().0);
+    }
+
+    #[test]
+    fn stamper_revokes_and_supersedes_stamps() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        for n in 1..=3 {
+            contract.stamp(sample_hash(n));
+        }
+        let record = contract.get_stamp_borsh(sample_hash(1));
+        context.block_timestamp = 200;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.revoke_stamp(sample_hash(1), "Draft withdrawn".to_string());
+        contract.supersede_stamp(sample_hash(2).to_uppercase(), sample_hash(3));
+
+        assert_eq!(
+            StampStatus::Revoked { reason: "Draft withdrawn".to_string(), timestamp: 200.into() },
+            contract.get_stamp(sample_hash(1)).unwrap().status
+        );
+        assert_eq!(
+            near_sdk::serde_json::json!({ "state": "superseded", "superseded_by": sample_hash(3), "timestamp": "200" }),
+            near_sdk::serde_json::to_value(contract.get_stamp(sample_hash(2)).unwrap().status).unwrap()
+        );
+        assert_eq!(StampStatus::Active, contract.get_stamp(sample_hash(3)).unwrap().status);
+        // The timestamp record is untouched
+        assert_eq!(record, contract.get_stamp_borsh(sample_hash(1)));
+        let names: Vec<_> = test_utils::get_events().iter().map(|event| event["event"].as_str().unwrap().to_string()).collect();
+        assert_eq!(vec!["stamp_revoked", "stamp_superseded"], names);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the original stamper can change the status of a stamp")]
+    fn only_the_stamper_revokes() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        context.predecessor_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.revoke_stamp(sample_hash(1), "Not mine".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "is no longer active")]
+    fn revoked_stamp_cant_be_superseded() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        contract.stamp(sample_hash(2));
+        contract.revoke_stamp(sample_hash(1), String::new());
+        contract.supersede_stamp(sample_hash(1), sample_hash(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be stamped first")]
+    fn replacement_must_be_stamped() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        contract.supersede_stamp(sample_hash(1), sample_hash(2));
+    }
+
+    #[test]
+    fn revisions_form_a_chain() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        assert_eq!(None, contract.get_revision(sample_hash(1)));
+        for n in 2..=4 {
+            contract.stamp_revision(sample_hash(n), sample_hash(n - 1));
+        }
+        assert!(contract.is_stamped(sample_hash(4)));
+        assert_eq!(
+            Some(revisions::RevisionView {
+                file_hash: sample_hash(2),
+                chain: sample_hash(1),
+                index: 1.into(),
+                parent: Some(sample_hash(1)),
+                next: Some(sample_hash(3)),
+                head: sample_hash(4),
+                length: 4.into(),
+            }),
+            contract.get_revision(sample_hash(2))
+        );
+        let head = contract.get_revision(sample_hash(4)).unwrap();
+        assert_eq!((Some(sample_hash(3)), None), (head.parent, head.next));
+        assert_eq!(None, contract.get_revision(sample_hash(1)).unwrap().parent);
+        assert_eq!(vec![sample_hash(2), sample_hash(3)], contract.get_revision_chain(sample_hash(4), 1, 2));
+        assert!(contract.get_revision_chain(sample_hash(5), 0, 10).is_empty());
+        let event = test_utils::get_events().pop().unwrap();
+        assert_eq!(
+            near_sdk::serde_json::json!([{ "file_hash": sample_hash(4), "parent_hash": sample_hash(3), "chain": sample_hash(1), "index": "3" }]),
+            event["data"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "was already revised, revise the latest version")]
+    fn only_the_chain_head_is_revised() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        contract.stamp_revision(sample_hash(2), sample_hash(1));
+        contract.stamp_revision(sample_hash(3), sample_hash(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Parent 'sha256:")]
+    fn revision_parent_must_be_stamped() {
+        testing_env!(get_context(vec![], false, 100));
+        new_contract().stamp_revision(sample_hash(2), sample_hash(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the stamper of")]
+    fn only_the_parent_stamper_revises() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        context.predecessor_account_id = "dave_near".to_string();
+        testing_env!(context);
+        contract.stamp_revision(sample_hash(2), sample_hash(1));
+    }
+
+    #[test]
+    fn stamps_are_indexed_by_account() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        for n in 0..30 {
+            contract.stamp(sample_hash(n));
+        }
+        context.block_timestamp = 200;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context.clone());
+        contract.stamp(sample_hash(0));
+        let root = contract.stamp_batch(vec![sample_hash(40), sample_hash(41)]);
+        contract.stamp_merkle_root(encode_hex(&[7; 32]), 5);
+        context.predecessor_account_id = "dave_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.stamp(sample_hash(50));
+
+        let carol: ValidAccountId = "carol_near".try_into().unwrap();
+        assert_eq!(33, contract.count_stamps_by_account(carol.clone()));
+        let stamp = |file_hash: String, timestamp: u64, seq: u64| account_index::AccountStamp { file_hash, timestamp: timestamp.into(), seq: seq.into() };
+        assert_eq!(
+            vec![stamp(sample_hash(29), 100, 29), stamp(sample_hash(0), 200, 30)],
+            contract.get_stamps_by_account(carol.clone(), 29, 2)
+        );
+        assert_eq!(
+            vec![stamp(format!("merkle-root:{}", root), 200, 31), stamp(format!("merkle-root:{}", encode_hex(&[7; 32])), 200, 32)],
+            contract.get_stamps_by_account(carol.clone(), 31, 10)
+        );
+        assert!(contract.get_stamps_by_account(carol, 33, 10).is_empty());
+        assert_eq!(vec![stamp(sample_hash(50), 200, 33)], contract.get_stamps_by_account("dave_near".try_into().unwrap(), 0, 10));
+        assert_eq!(0, contract.count_stamps_by_account("erin_near".try_into().unwrap()));
+    }
+
+    #[test]
+    fn stamps_are_listed_by_time_range() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        for (timestamp, stamper, n) in [(100, "carol_near", 1), (100, "dave_near", 2), (200, "carol_near", 3), (300, "dave_near", 4), (300, "carol_near", 1)].iter() {
+            context.block_timestamp = *timestamp;
+            context.predecessor_account_id = stamper.to_string();
+            context.storage_usage = env::storage_usage();
+            testing_env!(context.clone());
+            contract.stamp(sample_hash(*n));
+        }
+        let stamp = |n: u64, stamper: &str, timestamp: u64, seq: u64| time_index::TimedStamp {
+            file_hash: sample_hash(n),
+            stamper: stamper.to_string(),
+            timestamp: timestamp.into(),
+            seq: seq.into(),
+        };
+
+        // Pages through the stamps of [100, 300)
+        let page = contract.get_stamps_between(100.into(), 300.into(), 2, None);
+        assert_eq!(vec![stamp(1, "carol_near", 100, 0), stamp(2, "dave_near", 100, 1)], page.stamps);
+        let page = contract.get_stamps_between(100.into(), 300.into(), 2, page.next_cursor);
+        assert_eq!((vec![stamp(3, "carol_near", 200, 2)], None), (page.stamps, page.next_cursor));
+        let page = contract.get_stamps_between(150.into(), 301.into(), 10, None);
+        assert_eq!(vec![stamp(3, "carol_near", 200, 2), stamp(4, "dave_near", 300, 3), stamp(1, "carol_near", 300, 4)], page.stamps);
+        assert!(contract.get_stamps_between(300.into(), 300.into(), 10, None).stamps.is_empty());
+        // A cursor before `from_ns` doesn't widen the range, and any limit is capped
+        let page = contract.get_stamps_between(0.into(), 1000.into(), 1, None);
+        let page = contract.get_stamps_between(200.into(), 1000.into(), u64::MAX, page.next_cursor);
+        assert_eq!((vec![stamp(3, "carol_near", 200, 2), stamp(4, "dave_near", 300, 3), stamp(1, "carol_near", 300, 4)], None), (page.stamps, page.next_cursor));
+
+        let carol: ValidAccountId = "carol_near".try_into().unwrap();
+        let page = contract.get_account_stamps_between(carol.clone(), 0.into(), 1000.into(), 2, None);
+        assert_eq!(vec![stamp(1, "carol_near", 100, 0), stamp(3, "carol_near", 200, 2)], page.stamps);
+        let page = contract.get_account_stamps_between(carol.clone(), 0.into(), 1000.into(), 2, page.next_cursor);
+        assert_eq!((vec![stamp(1, "carol_near", 300, 4)], None), (page.stamps, page.next_cursor));
+        let page = contract.get_account_stamps_between(carol.clone(), 101.into(), 300.into(), 10, None);
+        assert_eq!((vec![stamp(3, "carol_near", 200, 2)], None), (page.stamps, page.next_cursor));
+        assert!(contract.get_account_stamps_between("erin_near".try_into().unwrap(), 0.into(), 1000.into(), 10, None).stamps.is_empty());
+        let page = contract.get_account_stamps_between(carol.clone(), 0.into(), 1000.into(), 1, None);
+        let page = contract.get_account_stamps_between(carol, 200.into(), 1000.into(), u64::MAX, page.next_cursor);
+        assert_eq!((vec![stamp(3, "carol_near", 200, 2), stamp(1, "carol_near", 300, 4)], None), (page.stamps, page.next_cursor));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid cursor")]
+    fn malformed_time_cursor_is_rejected() {
+        testing_env!(get_context(vec![], true, 100));
+        new_contract().get_stamps_between(0.into(), 1000.into(), 10, Some("100".to_string()));
+    }
+
+    #[test]
+    fn stamps_are_chained_in_the_log() {
+        let mut context = get_context(vec![], false, 100);
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        let genesis = encode_hex(&stamp_log::GENESIS_HEAD);
+        assert_eq!(stamp_log::LogHead { length: 0.into(), head: genesis.clone() }, contract.get_log_head());
+        contract.stamp(sample_hash(1));
+        context.block_timestamp = 200;
+        context.block_index = 7;
+        context.predecessor_account_id = "dave_near".to_string();
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.stamp(sample_hash(1));
+        let root = contract.stamp_batch(vec![sample_hash(2), sample_hash(3)]);
+
+        let segment = contract.get_log_segment(0.into(), 100.into());
+        let keys = vec![sample_hash(1), sample_hash(1), format!("merkle-root:{}", root)];
+        assert_eq!(keys, segment.iter().map(|entry| entry.file_hash.clone()).collect::<Vec<_>>());
+        let mut head = genesis;
+        for (seq, entry) in segment.iter().enumerate() {
+            assert_eq!((seq as u64, &head), (entry.seq.0, &entry.previous_head));
+            let preimage = stamp_log::log_entry_preimage(
+                &decode_hex(&head).unwrap(),
+                seq as u64,
+                entry.timestamp.0,
+                entry.block_height.0,
+                &entry.file_hash,
+                &entry.stamper,
+            );
+            head = encode_hex(&env::keccak256(&preimage));
+            assert_eq!(head, entry.head);
+            assert_eq!(Some(entry), contract.get_log_entry((seq as u64).into()).as_ref());
+        }
+        assert_eq!(("carol_near", 100, 0), (segment[0].stamper.as_str(), segment[0].timestamp.0, segment[0].block_height.0));
+        assert_eq!(("dave_near", 200, 7), (segment[2].stamper.as_str(), segment[2].timestamp.0, segment[2].block_height.0));
+        assert_eq!(stamp_log::LogHead { length: 3.into(), head }, contract.get_log_head());
+        assert_eq!(segment[1..2].to_vec(), contract.get_log_segment(1.into(), 2.into()));
+        assert!(contract.get_log_segment(2.into(), 1.into()).is_empty());
+        assert_eq!(None, contract.get_log_entry(3.into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "A segment has at most 100 entries")]
+    fn long_log_segment_is_rejected() {
+        testing_env!(get_context(vec![], true, 100));
+        new_contract().get_log_segment(5.into(), 106.into());
+    }
+
+    #[test]
+    fn stamps_are_leaves_of_the_transparency_log() {
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = 20 * STAMP_DEPOSIT;
+        testing_env!(context);
+        let mut contract = new_contract();
+        assert_eq!(encode_hex(&env::keccak256(b"")), contract.get_tree_head().root);
+        let mut heads = vec![];
+        for n in 0..13 {
+            contract.stamp(sample_hash(n % 5));
+            heads.push(contract.get_tree_head());
+        }
+        let leaves: Vec<_> = contract
+            .get_log_segment(0.into(), 13.into())
+            .iter()
+            .map(|entry| {
+                let data = transparency_log::log_leaf_data(entry.seq.0, entry.timestamp.0, entry.block_height.0, &entry.file_hash, &entry.stamper);
+                merkle::leaf_hash(&data)
+            })
+            .collect();
+        for (index, head) in heads.iter().enumerate() {
+            let size = index + 1;
+            assert_eq!(size as u64, head.size.0);
+            assert_eq!(encode_hex(&merkle::merkle_root(&leaves[..size]).unwrap()), head.root);
+            for leaf_index in 0..size {
+                let proof = contract.get_inclusion_proof((leaf_index as u64).into(), Some(head.size));
+                assert_eq!(encode_hex(&leaves[leaf_index]), proof.leaf_hash);
+                assert_eq!(merkle::merkle_proof(&leaves[..size], leaf_index).unwrap(), proof.proof);
+            }
+            for first in heads[..=index].iter() {
+                let proof = contract.get_consistency_proof(first.size, Some(head.size));
+                let (first_root, second_root) = (decode_hex(&first.root).unwrap(), decode_hex(&head.root).unwrap());
+                assert_eq!(Ok(()), merkle::verify_consistency(first.size.0, head.size.0, &first_root, &second_root, &proof));
+            }
+        }
+        assert_eq!(contract.get_inclusion_proof(3.into(), Some(13.into())), contract.get_inclusion_proof(3.into(), None));
+        assert_eq!(contract.get_consistency_proof(5.into(), Some(13.into())), contract.get_consistency_proof(5.into(), None));
+    }
+
+    #[test]
+    fn receipts_verify_offline() {
+        let mut context = get_context(vec![], false, 100);
+        context.attached_deposit = 10 * STAMP_DEPOSIT;
+        testing_env!(context.clone());
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        let file_hashes: Vec<_> = (2..7).map(sample_hash).collect();
+        let root = contract.stamp_batch(file_hashes.clone());
+        let (first_log_head, first_tree_head) = (contract.get_log_head(), contract.get_tree_head());
+        context.block_timestamp = 200;
+        context.block_index = 3;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        for n in 7..10 {
+            contract.stamp(sample_hash(n));
+        }
+        let (log_head, tree_head) = (contract.get_log_head(), contract.get_tree_head());
+
+        let mut stamp = contract.get_stamp(sample_hash(1)).unwrap();
+        assert_eq!(Ok(()), verifier::verify_commitment(&stamp));
+        stamp.timestamp = 101.into();
+        assert_eq!(Err(verifier::VerifyError::CommitmentMismatch { file_hash: sample_hash(1) }), verifier::verify_commitment(&stamp));
+
+        let batch_root = contract.get_batch_root(root).unwrap();
+        assert_eq!(Ok(()), verifier::verify_commitment(&batch_root.stamp));
+        let leaves: Vec<_> = file_hashes.iter().map(|file_hash| merkle::leaf_hash(file_hash.as_bytes())).collect();
+        let proof = merkle::merkle_proof(&leaves, 2).unwrap();
+        assert_eq!(Ok(()), verifier::verify_batch_inclusion(&file_hashes[2], &proof, &batch_root));
+        assert_eq!(Err(verifier::VerifyError::RootMismatch), verifier::verify_batch_inclusion(&file_hashes[3], &proof, &batch_root));
+
+        let entries = contract.get_log_segment(0.into(), 5.into());
+        assert_eq!(Ok(()), verifier::verify_log_segment(&encode_hex(&stamp_log::GENESIS_HEAD), &entries[..2], &first_log_head.head));
+        assert_eq!(Ok(()), verifier::verify_log_segment(&entries[2].previous_head, &entries[2..], &log_head.head));
+        assert_eq!(Err(verifier::VerifyError::HeadMismatch), verifier::verify_log_segment(&entries[0].previous_head, &entries[..4], &log_head.head));
+        let mut forged = entries.clone();
+        forged[3].stamper = "mallory_near".to_string();
+        assert_eq!(Err(verifier::VerifyError::BrokenChain { seq: 3 }), verifier::verify_log_segment(&forged[0].previous_head, &forged, &log_head.head));
+        forged.remove(3);
+        assert_eq!(Err(verifier::VerifyError::BrokenChain { seq: 4 }), verifier::verify_log_segment(&forged[0].previous_head, &forged, &log_head.head));
+
+        for entry in entries.iter() {
+            let proof = contract.get_inclusion_proof(entry.seq, None);
+            assert_eq!(Ok(()), verifier::verify_log_inclusion(entry, &proof, &tree_head));
+        }
+        let proof = contract.get_inclusion_proof(1.into(), Some(first_tree_head.size));
+        assert_eq!(Ok(()), verifier::verify_log_inclusion(&entries[1], &proof, &first_tree_head));
+        assert_eq!(
+            Err(verifier::VerifyError::TreeSizeMismatch { proof: 2, head: 5 }),
+            verifier::verify_log_inclusion(&entries[1], &proof, &tree_head)
+        );
+        assert_eq!(Err(verifier::VerifyError::LeafMismatch { leaf_index: 0 }), verifier::verify_log_inclusion(&entries[0], &proof, &first_tree_head));
+
+        let proof = contract.get_consistency_proof(first_tree_head.size, None);
+        assert_eq!(Ok(()), verifier::verify_log_consistency(&first_tree_head, &tree_head, &proof));
+        let rewritten = transparency_log::TreeHead { size: tree_head.size, root: encode_hex(&[0; 32]) };
+        assert_eq!(
+            Err(verifier::VerifyError::Merkle(merkle::MerkleError::InconsistentTrees { first_size: 2, second_size: 5 })),
+            verifier::verify_log_consistency(&first_tree_head, &rewritten, &proof)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Leaf 2 is out of range for a tree of 2 leaves")]
+    fn inclusion_proof_leaf_must_be_in_the_tree() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        contract.stamp(sample_hash(2));
+        contract.get_inclusion_proof(2.into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Tree size 2 is larger than the current tree of 1 leaves")]
+    fn proofs_are_only_given_for_published_trees() {
+        testing_env!(get_context(vec![], false, 100));
+        let mut contract = new_contract();
+        contract.stamp(sample_hash(1));
+        contract.get_consistency_proof(1.into(), Some(2.into()));
+    }
 }
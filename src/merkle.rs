@@ -0,0 +1,395 @@
+//! Merkle trees used to stamp many file hashes under a single root.
+//!
+//! Leaves and inner nodes are hashed with keccak256, the hash of the commitment, under
+//! distinct one byte prefixes so that a leaf can never be passed off as an inner node:
+//!
+//! ```text
+//! leaf = keccak256(0x00 || canonical file hash)
+//! node = keccak256(0x01 || left || right)
+//! ```
+//!
+//! Each level pairs nodes from left to right, and the last node of a level with an odd number
+//! of nodes is moved up unchanged. This is the tree of RFC 6962, section 2.1.
+//!
+//! The same trees make up the append-only log of the `transparency_log` module, whose
+//! consistency proofs are checked by `verify_consistency`.
+
+use crate::file_hash::{decode_hex, encode_hex};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+#[cfg(target_arch = "wasm32")]
+use near_sdk::env;
+use near_sdk::serde::{Deserialize, Serialize};
+use std::fmt;
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const HASH_LEN: usize = 32;
+/// Deepest proof accepted, enough for 2^64 leaves.
+pub const MAX_PROOF_DEPTH: usize = 64;
+
+/// Side of a proof sibling relative to the node it is hashed with.
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Proof that a leaf is included under a root: the siblings met on the way from the leaf up
+/// to the root, and on which side of the path each of them is.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MerkleProof {
+    /// Hex encoded sibling hashes, the leaf's sibling first.
+    pub siblings: Vec<String>,
+    pub sides: Vec<Side>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MerkleError {
+    EmptyTree,
+    LeafOutOfRange { index: usize, leaf_count: usize },
+    MismatchedDirections { siblings: usize, sides: usize },
+    TooDeep(usize),
+    InvalidSibling(usize),
+    InconsistentTrees { first_size: u64, second_size: u64 },
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MerkleError::EmptyTree => write!(f, "A Merkle tree needs at least one leaf"),
+            MerkleError::LeafOutOfRange { index, leaf_count } => {
+                write!(f, "Leaf {} is out of range for a tree of {} leaves", index, leaf_count)
+            }
+            MerkleError::MismatchedDirections { siblings, sides } => {
+                write!(f, "Proof has {} siblings but {} sides", siblings, sides)
+            }
+            MerkleError::TooDeep(depth) => {
+                write!(f, "Proof has {} siblings, at most {} are accepted", depth, MAX_PROOF_DEPTH)
+            }
+            MerkleError::InvalidSibling(index) => write!(f, "Proof sibling {} is not a hex encoded 32 byte hash", index),
+            MerkleError::InconsistentTrees { first_size, second_size } => {
+                write!(f, "Proof doesn't show that the tree of {} leaves is a prefix of the tree of {} leaves", first_size, second_size)
+            }
+        }
+    }
+}
+
+/// keccak256 of the host on chain, computed in Rust elsewhere so that the `verifier` module
+/// checks proofs without a NEAR environment.
+#[cfg(target_arch = "wasm32")]
+pub fn keccak256(data: &[u8]) -> Vec<u8> {
+    env::keccak256(data)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn keccak256(data: &[u8]) -> Vec<u8> {
+    use sha3::{Digest, Keccak256};
+    Keccak256::digest(data).to_vec()
+}
+
+pub fn leaf_hash(data: &[u8]) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(1 + data.len());
+    preimage.push(LEAF_PREFIX);
+    preimage.extend(data);
+    keccak256(&preimage)
+}
+
+pub fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(1 + left.len() + right.len());
+    preimage.push(NODE_PREFIX);
+    preimage.extend(left);
+    preimage.extend(right);
+    keccak256(&preimage)
+}
+
+/// Root of the tree over the given leaf hashes.
+pub fn merkle_root(leaves: &[Vec<u8>]) -> Result<Vec<u8>, MerkleError> {
+    if leaves.is_empty() {
+        return Err(MerkleError::EmptyTree);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    Ok(level.remove(0))
+}
+
+/// Inclusion proof of the leaf at `index` among the given leaf hashes.
+pub fn merkle_proof(leaves: &[Vec<u8>], mut index: usize) -> Result<MerkleProof, MerkleError> {
+    if index >= leaves.len() {
+        return Err(MerkleError::LeafOutOfRange { index, leaf_count: leaves.len() });
+    }
+    let mut proof = MerkleProof { siblings: vec![], sides: vec![] };
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        // The last node of an odd level has no sibling and moves up as is
+        if sibling < level.len() {
+            proof.siblings.push(encode_hex(&level[sibling]));
+            proof.sides.push(if sibling < index { Side::Left } else { Side::Right });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Ok(proof)
+}
+
+impl MerkleProof {
+    /// Root of the tree in which `leaf`, a leaf hash, is at the position this proof describes.
+    pub fn compute_root(&self, leaf: &[u8]) -> Result<Vec<u8>, MerkleError> {
+        if self.siblings.len() != self.sides.len() {
+            return Err(MerkleError::MismatchedDirections { siblings: self.siblings.len(), sides: self.sides.len() });
+        }
+        if self.siblings.len() > MAX_PROOF_DEPTH {
+            return Err(MerkleError::TooDeep(self.siblings.len()));
+        }
+        let mut node = leaf.to_vec();
+        for (index, (sibling, side)) in self.siblings.iter().zip(&self.sides).enumerate() {
+            let sibling = decode_hex(sibling)
+                .ok()
+                .filter(|sibling| sibling.len() == HASH_LEN)
+                .ok_or(MerkleError::InvalidSibling(index))?;
+            node = match side {
+                Side::Left => node_hash(&sibling, &node),
+                Side::Right => node_hash(&node, &sibling),
+            };
+        }
+        Ok(node)
+    }
+}
+
+/// Checks that the tree of `first_size` leaves under `first_root` is the prefix of the tree of
+/// `second_size` leaves under `second_root`, given the hex encoded hashes of a consistency
+/// proof of RFC 6962, section 2.1.2. Follows the verification of RFC 9162, section 2.1.4.2.
+pub fn verify_consistency(
+    first_size: u64,
+    second_size: u64,
+    first_root: &[u8],
+    second_root: &[u8],
+    proof: &[String],
+) -> Result<(), MerkleError> {
+    let inconsistent = MerkleError::InconsistentTrees { first_size, second_size };
+    if first_size == 0 {
+        return Err(MerkleError::EmptyTree);
+    }
+    if proof.len() > MAX_PROOF_DEPTH {
+        return Err(MerkleError::TooDeep(proof.len()));
+    }
+    let mut hashes = proof
+        .iter()
+        .enumerate()
+        .map(|(index, hash)| decode_hex(hash).ok().filter(|hash| hash.len() == HASH_LEN).ok_or(MerkleError::InvalidSibling(index)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if first_size == second_size {
+        return if hashes.is_empty() && first_root == second_root { Ok(()) } else { Err(inconsistent) };
+    }
+    if first_size > second_size || hashes.is_empty() {
+        return Err(inconsistent);
+    }
+    // The first tree is a perfect subtree of the second one, its root is left out of the proof
+    if first_size.is_power_of_two() {
+        hashes.insert(0, first_root.to_vec());
+    }
+    let (mut first_index, mut second_index) = (first_size - 1, second_size - 1);
+    while first_index & 1 == 1 {
+        first_index >>= 1;
+        second_index >>= 1;
+    }
+    let (mut first_node, mut second_node) = (hashes[0].clone(), hashes[0].clone());
+    for hash in &hashes[1..] {
+        if second_index == 0 {
+            return Err(inconsistent);
+        }
+        if first_index & 1 == 1 || first_index == second_index {
+            first_node = node_hash(hash, &first_node);
+            second_node = node_hash(hash, &second_node);
+            while first_index & 1 == 0 && first_index != 0 {
+                first_index >>= 1;
+                second_index >>= 1;
+            }
+        } else {
+            second_node = node_hash(&second_node, hash);
+        }
+        first_index >>= 1;
+        second_index >>= 1;
+    }
+    if first_node == first_root && second_node == second_root && second_index == 0 {
+        Ok(())
+    } else {
+        Err(inconsistent)
+    }
+}
+
+fn next_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{testing_env, MockedBlockchain, VMContext};
+
+    fn setup() {
+        let context = VMContext {
+            current_account_id: "alice_near".to_string(),
+            signer_account_id: "bob_near".to_string(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id: "carol_near".to_string(),
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 0,
+            account_locked_balance: 0,
+            storage_usage: 0,
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: true,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        };
+        testing_env!(context);
+    }
+
+    fn leaves(count: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|i| leaf_hash(format!("leaf {}", i).as_bytes())).collect()
+    }
+
+    /// MTH of RFC 6962, section 2.1, over leaf hashes
+    fn rfc6962_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+        if leaves.len() == 1 {
+            return leaves[0].clone();
+        }
+        let split = leaves.len().next_power_of_two() / 2;
+        node_hash(&rfc6962_root(&leaves[..split]), &rfc6962_root(&leaves[split..]))
+    }
+
+    #[test]
+    fn root_matches_rfc6962() {
+        setup();
+        assert_eq!(Err(MerkleError::EmptyTree), merkle_root(&[]));
+        for count in 1..=17 {
+            assert_eq!(rfc6962_root(&leaves(count)), merkle_root(&leaves(count)).unwrap());
+        }
+    }
+
+    #[test]
+    fn proofs_of_every_leaf_verify() {
+        setup();
+        for count in 1..=17 {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, index).unwrap();
+                assert_eq!(root, proof.compute_root(leaf).unwrap(), "leaf {} of {}", index, count);
+                // A proof only holds for its own leaf
+                if count > 1 {
+                    assert_ne!(root, proof.compute_root(&leaves[(index + 1) % count]).unwrap());
+                }
+            }
+            assert_eq!(
+                Err(MerkleError::LeafOutOfRange { index: count, leaf_count: count }),
+                merkle_proof(&leaves, count)
+            );
+        }
+    }
+
+    /// PROOF of RFC 6962, section 2.1.2, over leaf hashes
+    fn rfc6962_consistency(first_size: usize, leaves: &[Vec<u8>], complete: bool) -> Vec<String> {
+        if first_size == leaves.len() {
+            return if complete { vec![] } else { vec![encode_hex(&rfc6962_root(leaves))] };
+        }
+        let split = leaves.len().next_power_of_two() / 2;
+        let (mut proof, sibling) = if first_size <= split {
+            (rfc6962_consistency(first_size, &leaves[..split], complete), &leaves[split..])
+        } else {
+            (rfc6962_consistency(first_size - split, &leaves[split..], false), &leaves[..split])
+        };
+        proof.push(encode_hex(&rfc6962_root(sibling)));
+        proof
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        setup();
+        let leaves = leaves(17);
+        for second_size in 1..=17 {
+            let second_root = merkle_root(&leaves[..second_size]).unwrap();
+            for first_size in 1..=second_size {
+                let first_root = merkle_root(&leaves[..first_size]).unwrap();
+                let proof = rfc6962_consistency(first_size, &leaves[..second_size], true);
+                let (first, second) = (first_size as u64, second_size as u64);
+                assert_eq!(Ok(()), verify_consistency(first, second, &first_root, &second_root, &proof), "{} of {}", first, second);
+                // The proof holds for these roots and sizes only
+                let inconsistent = Err(MerkleError::InconsistentTrees { first_size: first, second_size: second });
+                let other_root = leaf_hash(b"other");
+                assert_eq!(inconsistent, verify_consistency(first, second, &other_root, &second_root, &proof));
+                assert_eq!(inconsistent, verify_consistency(first, second, &first_root, &other_root, &proof));
+                if second_size > 1 {
+                    let other_first = if first == 1 { 2 } else { first - 1 };
+                    assert!(verify_consistency(other_first, second, &first_root, &second_root, &proof).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_consistency_proofs_are_rejected() {
+        setup();
+        let leaves = leaves(6);
+        let (first_root, second_root) = (merkle_root(&leaves[..3]).unwrap(), merkle_root(&leaves).unwrap());
+        let mut proof = rfc6962_consistency(3, &leaves, true);
+        assert_eq!(Err(MerkleError::EmptyTree), verify_consistency(0, 6, &first_root, &second_root, &proof));
+        assert_eq!(
+            Err(MerkleError::InconsistentTrees { first_size: 6, second_size: 3 }),
+            verify_consistency(6, 3, &second_root, &first_root, &proof)
+        );
+        proof[1] = "abcd".to_string();
+        assert_eq!(Err(MerkleError::InvalidSibling(1)), verify_consistency(3, 6, &first_root, &second_root, &proof));
+        let mut proof = rfc6962_consistency(3, &leaves, true);
+        proof.pop();
+        assert_eq!(
+            Err(MerkleError::InconsistentTrees { first_size: 3, second_size: 6 }),
+            verify_consistency(3, 6, &first_root, &second_root, &proof)
+        );
+        // Equal trees need an empty proof
+        let proof = vec![encode_hex(&second_root)];
+        assert!(verify_consistency(6, 6, &second_root, &second_root, &proof).is_err());
+        assert_eq!(Ok(()), verify_consistency(6, 6, &second_root, &second_root, &[]));
+    }
+
+    #[test]
+    fn odd_node_moves_up_without_sibling() {
+        setup();
+        let leaves = leaves(5);
+        let proof = merkle_proof(&leaves, 4).unwrap();
+        assert_eq!(vec![Side::Left], proof.sides);
+        assert_eq!(vec![encode_hex(&merkle_root(&leaves[..4]).unwrap())], proof.siblings);
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        setup();
+        let leaves = leaves(4);
+        let mut proof = merkle_proof(&leaves, 1).unwrap();
+        proof.sides.pop();
+        assert_eq!(Err(MerkleError::MismatchedDirections { siblings: 2, sides: 1 }), proof.compute_root(&leaves[1]));
+        let proof = MerkleProof { siblings: vec!["abcd".to_string()], sides: vec![Side::Left] };
+        assert_eq!(Err(MerkleError::InvalidSibling(0)), proof.compute_root(&leaves[1]));
+        let proof = MerkleProof { siblings: vec![encode_hex(&leaves[0]); 65], sides: vec![Side::Left; 65] };
+        assert_eq!(Err(MerkleError::TooDeep(65)), proof.compute_root(&leaves[1]));
+        // Flipping a side leads to another root
+        let mut proof = merkle_proof(&leaves, 1).unwrap();
+        proof.sides[0] = Side::Right;
+        assert_ne!(merkle_root(&leaves).unwrap(), proof.compute_root(&leaves[1]).unwrap());
+    }
+}
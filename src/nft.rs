@@ -0,0 +1,289 @@
+//! Certificates: NEP-171 non-fungible tokens minted for stamped file hashes.
+//!
+//! While certificates are enabled by the owner, the stamp that creates the record of a file
+//! hash also mints a certificate to the stamper. The token ID is the canonical file hash, and
+//! its NEP-177 metadata is built from the record whenever it is viewed, so the certificate
+//! always shows the original timestamp and commitment. Transferring a certificate only changes
+//! who holds it, the record and its history are never touched.
+//!
+//! Approvals (NEP-178) are not supported, so `approval_id` must be `null`.
+
+use crate::events::{NftEvent, NftMintData, NftTransferData};
+use crate::file_hash::encode_hex;
+use crate::storage::assert_one_yocto;
+use crate::views::format_rfc3339;
+use crate::{ProofOfTimestamp, TimestampedFile, OWNER_CERTIFICATES_PREFIX};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, json};
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Gas, Promise, PromiseResult};
+use std::collections::HashMap;
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+pub const NFT_METADATA_SPEC: &str = "nft-1.0.0";
+const GAS_FOR_RESOLVE_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_NFT_TRANSFER_CALL: Gas = 25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER;
+
+pub type TokenId = String;
+
+/// NEP-177 contract metadata.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NFTContractMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>,
+}
+
+/// NEP-177 token metadata.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub media: Option<String>,
+    pub media_hash: Option<String>,
+    pub copies: Option<u64>,
+    /// Stamp timestamp, in milliseconds since the Unix epoch.
+    pub issued_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub starts_at: Option<String>,
+    pub updated_at: Option<String>,
+    /// JSON object of the file hash, the timestamp in nanoseconds and the commitment.
+    pub extra: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Token {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub metadata: TokenMetadata,
+}
+
+#[ext_contract(ext_receiver)]
+pub trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(&mut self, sender_id: AccountId, previous_owner_id: AccountId, token_id: TokenId, msg: String) -> bool;
+}
+
+#[ext_contract(ext_self)]
+pub trait NonFungibleTokenResolver {
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool;
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Mint a certificate with every new record, or stop minting them. Owner only.
+    pub fn set_certificates_enabled(&mut self, enabled: bool) {
+        self.assert_owner();
+        self.certificates_enabled = enabled;
+    }
+
+    pub fn certificates_enabled(&self) -> bool {
+        self.certificates_enabled
+    }
+
+    /// Transfers the certificate `token_id` held by the predecessor to `receiver_id`.
+    /// Requires a deposit of exactly 1 yoctoNEAR.
+    #[payable]
+    pub fn nft_transfer(&mut self, receiver_id: ValidAccountId, token_id: TokenId, approval_id: Option<u64>, memo: Option<String>) {
+        assert_one_yocto();
+        self.transfer_certificate(&env::predecessor_account_id(), receiver_id.as_ref(), &token_id, approval_id, memo);
+    }
+
+    /// Transfers the certificate `token_id` to `receiver_id` and calls its `nft_on_transfer`,
+    /// the transfer is reverted if it returns `true` or fails. Requires a deposit of exactly
+    /// 1 yoctoNEAR.
+    #[payable]
+    pub fn nft_transfer_call(
+        &mut self,
+        receiver_id: ValidAccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> Promise {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let receiver_id: AccountId = receiver_id.into();
+        self.transfer_certificate(&sender_id, &receiver_id, &token_id, approval_id, memo);
+        ext_receiver::nft_on_transfer(
+            sender_id.clone(),
+            sender_id.clone(),
+            token_id.clone(),
+            msg,
+            &receiver_id,
+            0,
+            env::prepaid_gas().saturating_sub(GAS_FOR_NFT_TRANSFER_CALL),
+        )
+        .then(ext_self::nft_resolve_transfer(
+            sender_id,
+            receiver_id,
+            token_id,
+            None,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Callback of `nft_transfer_call`, returns whether the certificate stays with `receiver_id`.
+    pub fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Method is private");
+        // Part of the NEP-171 interface, there are no approvals to restore
+        let _ = approved_account_ids;
+        let must_revert = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<bool>(&value).unwrap_or(true),
+            _ => true,
+        };
+        // The receiver may already have passed the certificate on
+        if !must_revert || self.certificates.get(&token_id).as_ref() != Some(&receiver_id) {
+            return true;
+        }
+        self.move_certificate(&receiver_id, &previous_owner_id, &token_id, None);
+        false
+    }
+
+    pub fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.certificates.get(&token_id).map(|owner_id| self.certificate(token_id, owner_id))
+    }
+
+    pub fn nft_total_supply(&self) -> U128 {
+        U128(self.certificate_count.into())
+    }
+
+    pub fn nft_supply_for_owner(&self, account_id: ValidAccountId) -> U128 {
+        U128(self.certificates_per_owner.get(account_id.as_ref()).map_or(0, |tokens| tokens.len()).into())
+    }
+
+    /// Returns up to `limit` certificates of `account_id`, starting at index `from_index`.
+    pub fn nft_tokens_for_owner(&self, account_id: ValidAccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let tokens = match self.certificates_per_owner.get(account_id.as_ref()) {
+            Some(tokens) => tokens,
+            None => return vec![],
+        };
+        let tokens = tokens.as_vector();
+        let from = from_index.map_or(0, |from_index| from_index.0.min(u64::MAX.into()) as u64);
+        (from..std::cmp::min(from.saturating_add(limit.unwrap_or(u64::MAX)), tokens.len()))
+            .map(|index| self.certificate(tokens.get(index).unwrap(), account_id.as_ref().clone()))
+            .collect()
+    }
+
+    pub fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Proof of Timestamp certificates".to_string(),
+            symbol: "POT".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Mints the certificate of the newly stamped `file_hash` to `owner_id`.
+    pub(crate) fn mint_certificate(&mut self, file_hash: &str, owner_id: &AccountId) {
+        let token_id = file_hash.to_string();
+        self.certificates.insert(&token_id, owner_id);
+        self.add_certificate_to_owner(owner_id, &token_id);
+        self.certificate_count += 1;
+        NftEvent::NftMint(vec![NftMintData { owner_id: owner_id.clone(), token_ids: vec![token_id] }]).emit();
+    }
+
+    fn transfer_certificate(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        assert!(approval_id.is_none(), "Approvals are not supported");
+        let owner_id = self
+            .certificates
+            .get(token_id)
+            .unwrap_or_else(|| env::panic(format!("Certificate '{}' doesn't exist", token_id).as_bytes()));
+        assert_eq!(sender_id, &owner_id, "Only the holder of a certificate can transfer it");
+        assert_ne!(sender_id, receiver_id, "Can't transfer a certificate to its holder");
+        self.move_certificate(&owner_id, receiver_id, token_id, memo);
+    }
+
+    fn move_certificate(&mut self, from: &AccountId, to: &AccountId, token_id: &TokenId, memo: Option<String>) {
+        let mut tokens = self.certificates_per_owner.get(from).unwrap();
+        tokens.remove(token_id);
+        if tokens.is_empty() {
+            self.certificates_per_owner.remove(from);
+        } else {
+            self.certificates_per_owner.insert(from, &tokens);
+        }
+        self.add_certificate_to_owner(to, token_id);
+        self.certificates.insert(token_id, to);
+        NftEvent::NftTransfer(vec![NftTransferData {
+            old_owner_id: from.clone(),
+            new_owner_id: to.clone(),
+            token_ids: vec![token_id.clone()],
+            memo,
+        }])
+        .emit();
+    }
+
+    fn add_certificate_to_owner(&mut self, owner_id: &AccountId, token_id: &TokenId) {
+        let mut tokens = self.certificates_per_owner.get(owner_id).unwrap_or_else(|| {
+            let mut prefix = OWNER_CERTIFICATES_PREFIX.to_vec();
+            prefix.extend(env::sha256(owner_id.as_bytes()));
+            UnorderedSet::new(prefix)
+        });
+        tokens.insert(token_id);
+        self.certificates_per_owner.insert(owner_id, &tokens);
+    }
+
+    fn certificate(&self, token_id: TokenId, owner_id: AccountId) -> Token {
+        let record: TimestampedFile = self.records.get(&token_id).unwrap().into();
+        let metadata = TokenMetadata {
+            title: Some(format!("Proof of timestamp of {}", token_id)),
+            description: Some(format!("{} was stamped on NEAR at {}", token_id, format_rfc3339(record.timestamp))),
+            media: None,
+            media_hash: None,
+            copies: Some(1),
+            issued_at: Some((record.timestamp / 1_000_000).to_string()),
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: Some(
+                json!({
+                    "file_hash": token_id,
+                    "timestamp": record.timestamp.to_string(),
+                    "commitment": encode_hex(&record.time_stamped_file_hash),
+                    "commitment_version": record.commitment_version,
+                })
+                .to_string(),
+            ),
+            reference: None,
+            reference_hash: None,
+        };
+        Token { token_id, owner_id, metadata }
+    }
+}
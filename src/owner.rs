@@ -0,0 +1,79 @@
+//! Ownership of the contract. The owner can pause stamping, e.g. during an incident or before
+//! a migration, and hands the contract over in two steps: it proposes a new owner, which then
+//! accepts, so that ownership can't be lost to a mistyped account ID.
+//!
+//! Views and storage management keep working while the contract is paused.
+
+use crate::events::{Event, OwnershipProposedData, OwnershipTransferredData, PauseData};
+use crate::ProofOfTimestamp;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{env, near_bindgen, AccountId};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_proposed_owner(&self) -> Option<AccountId> {
+        self.proposed_owner_id.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Rejects every stamp until `unpause`. Owner only.
+    pub fn pause(&mut self) {
+        self.assert_owner();
+        assert!(!self.paused, "Contract is already paused");
+        self.paused = true;
+        Event::Paused(vec![PauseData { by: env::predecessor_account_id() }]).emit();
+    }
+
+    /// Owner only.
+    pub fn unpause(&mut self) {
+        self.assert_owner();
+        assert!(self.paused, "Contract is not paused");
+        self.paused = false;
+        Event::Unpaused(vec![PauseData { by: env::predecessor_account_id() }]).emit();
+    }
+
+    /// Proposes `new_owner_id` as the next owner, which takes over once it calls
+    /// `accept_owner`. `null` withdraws the pending proposal. Owner only.
+    pub fn propose_owner(&mut self, new_owner_id: Option<ValidAccountId>) {
+        self.assert_owner();
+        self.proposed_owner_id = new_owner_id.map(Into::into);
+        Event::OwnershipProposed(vec![OwnershipProposedData {
+            owner_id: self.owner_id.clone(),
+            proposed_owner_id: self.proposed_owner_id.clone(),
+        }])
+        .emit();
+    }
+
+    /// Makes the predecessor the owner, if it is the proposed owner.
+    pub fn accept_owner(&mut self) {
+        let account_id = env::predecessor_account_id();
+        assert_eq!(
+            Some(&account_id),
+            self.proposed_owner_id.as_ref(),
+            "Only the proposed owner can accept ownership"
+        );
+        let old_owner_id = std::mem::replace(&mut self.owner_id, account_id.clone());
+        self.proposed_owner_id = None;
+        Event::OwnershipTransferred(vec![OwnershipTransferredData { old_owner_id, new_owner_id: account_id }]).emit();
+    }
+}
+
+impl ProofOfTimestamp {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only the owner can call this method");
+    }
+
+    pub(crate) fn assert_not_paused(&self) {
+        assert!(!self.paused, "Stamping is paused");
+    }
+}
@@ -0,0 +1,126 @@
+//! Revision chains: file hashes stamped as successive versions of a document.
+//!
+//! `stamp_revision` stamps a new file hash as the revision of an already stamped parent. The
+//! versions of a document form a linear chain, named after its first version: only the head of
+//! a chain can be revised, and only by the account that stamped it. Otherwise any account could
+//! append its own version to the head of someone else's document and take over its lineage.
+//! Each chain is stored as a vector of its versions, so that its head, its length and the
+//! neighbours of any version are read in constant time.
+
+use crate::events::{Event, RevisionData};
+use crate::{parse_file_hash, ProofOfTimestamp, TimestampedFile, REVISION_CHAIN_VERSIONS_PREFIX};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+/// Place of a file hash in its revision chain.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RevisionPosition {
+    /// First version of the chain, which names it.
+    chain: String,
+    index: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RevisionView {
+    pub file_hash: String,
+    /// First version of the chain.
+    pub chain: String,
+    /// Position of `file_hash` in the chain, the first version being 0.
+    pub index: U64,
+    pub parent: Option<String>,
+    pub next: Option<String>,
+    /// Latest version of the chain.
+    pub head: String,
+    pub length: U64,
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Stamps `file_hash` as the next version of `parent_hash`, which must be stamped and be the
+    /// latest version of its chain. Only the stamper of `parent_hash` can revise it, and
+    /// `file_hash` must not be stamped yet. Paid like `stamp`.
+    #[payable]
+    pub fn stamp_revision(&mut self, file_hash: String, parent_hash: String) {
+        let stamper = env::predecessor_account_id();
+        self.assert_can_stamp(&stamper);
+        let initial_storage_usage = env::storage_usage();
+        let parent_hash = self.record_key(&parent_hash);
+        let parent: TimestampedFile = self
+            .records
+            .get(&parent_hash)
+            .unwrap_or_else(|| env::panic(format!("Parent '{}' must be stamped first", parent_hash).as_bytes()))
+            .into();
+        assert_eq!(Some(&stamper), parent.stamper.as_ref(), "Only the stamper of '{}' can stamp its revisions", parent_hash);
+        let file_hash = parse_file_hash(&file_hash).canonical();
+        assert!(!self.records.contains_key(&file_hash), "'{}' is already stamped, a revision must be a new file hash", file_hash);
+
+        let (chain, mut versions) = match self.revisions.get(&parent_hash) {
+            Some(position) => {
+                let versions = self.revision_chains.get(&position.chain).unwrap();
+                if position.index + 1 != versions.len() {
+                    env::panic(
+                        format!("'{}' was already revised, revise the latest version '{}'", parent_hash, last(&versions)).as_bytes(),
+                    );
+                }
+                (position.chain, versions)
+            }
+            None => {
+                let mut prefix = REVISION_CHAIN_VERSIONS_PREFIX.to_vec();
+                prefix.extend(env::sha256(parent_hash.as_bytes()));
+                let mut versions = Vector::new(prefix);
+                versions.push(&parent_hash);
+                self.revisions.insert(&parent_hash, &RevisionPosition { chain: parent_hash.clone(), index: 0 });
+                (parent_hash.clone(), versions)
+            }
+        };
+        self.stamp_file_hash(&file_hash, &stamper);
+        versions.push(&file_hash);
+        let index = versions.len() - 1;
+        self.revisions.insert(&file_hash, &RevisionPosition { chain: chain.clone(), index });
+        self.revision_chains.insert(&chain, &versions);
+        Event::RevisionStamped(vec![RevisionData { file_hash, parent_hash, chain, index: index.into() }]).emit();
+
+        let deposit = self.collect_fee(self.fee_schedule.stamp_fee.0);
+        self.charge_storage(&stamper, deposit, initial_storage_usage);
+    }
+
+    /// Place of `file_hash` in its revision chain, `None` if it has no parent nor revision.
+    pub fn get_revision(&self, file_hash: String) -> Option<RevisionView> {
+        let file_hash = self.record_key(&file_hash);
+        let position = self.revisions.get(&file_hash)?;
+        let versions = self.revision_chains.get(&position.chain).unwrap();
+        Some(RevisionView {
+            parent: position.index.checked_sub(1).and_then(|index| versions.get(index)),
+            next: versions.get(position.index + 1),
+            head: last(&versions),
+            length: versions.len().into(),
+            file_hash,
+            chain: position.chain,
+            index: position.index.into(),
+        })
+    }
+
+    /// Returns up to `limit` versions of the chain of `file_hash`, oldest first, starting at
+    /// index `from`. Walk backward from a version by reading the indexes before its own.
+    pub fn get_revision_chain(&self, file_hash: String, from: u64, limit: u64) -> Vec<String> {
+        let file_hash = self.record_key(&file_hash);
+        let versions = match self.revisions.get(&file_hash) {
+            Some(position) => self.revision_chains.get(&position.chain).unwrap(),
+            None => return vec![],
+        };
+        (from..std::cmp::min(from.saturating_add(limit), versions.len()))
+            .map(|index| versions.get(index).unwrap())
+            .collect()
+    }
+}
+
+fn last(versions: &Vector<String>) -> String {
+    versions.get(versions.len() - 1).unwrap()
+}
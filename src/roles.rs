@@ -0,0 +1,139 @@
+//! Role-based access control.
+//!
+//! - Admins manage the notary and auditor roles. Only the owner grants and revokes the admin
+//!   role, and the owner can do everything an admin can. Only the owner switches between the
+//!   open and the permissioned mode, which decides who may stamp at all.
+//! - Notaries are the accounts allowed to stamp in permissioned mode. In open mode, the
+//!   default, anyone can stamp.
+//! - Auditors are accredited to review stamps. The role grants no method on chain, it records
+//!   the accreditation where verifiers can look it up.
+//!
+//! Every role change emits a `role_granted` or `role_revoked` event.
+
+use crate::events::{Event, RoleData, StampingModeData};
+use crate::{ProofOfTimestamp, ROLE_MEMBERS_PREFIX};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    Notary,
+    Auditor,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Admin, Role::Notary, Role::Auditor];
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Grants `role` to `account_id`, returns `false` if it already had it.
+    pub fn grant_role(&mut self, role: Role, account_id: ValidAccountId) -> bool {
+        self.assert_role_manager(role);
+        let account_id: AccountId = account_id.into();
+        let mut members = self.role_members(role);
+        let granted = members.insert(&account_id);
+        if granted {
+            self.roles.insert(&role, &members);
+            Event::RoleGranted(vec![RoleData { role, account_id, by: env::predecessor_account_id() }]).emit();
+        }
+        granted
+    }
+
+    /// Revokes `role` from `account_id`, returns `false` if it didn't have it.
+    pub fn revoke_role(&mut self, role: Role, account_id: ValidAccountId) -> bool {
+        self.assert_role_manager(role);
+        let account_id: AccountId = account_id.into();
+        let mut members = self.role_members(role);
+        let revoked = members.remove(&account_id);
+        if revoked {
+            self.roles.insert(&role, &members);
+            Event::RoleRevoked(vec![RoleData { role, account_id, by: env::predecessor_account_id() }]).emit();
+        }
+        revoked
+    }
+
+    /// In permissioned mode only notaries can stamp, in open mode anyone can. Owner only.
+    pub fn set_permissioned(&mut self, permissioned: bool) {
+        self.assert_owner();
+        if self.permissioned != permissioned {
+            self.permissioned = permissioned;
+            Event::StampingModeChanged(vec![StampingModeData { permissioned, by: env::predecessor_account_id() }]).emit();
+        }
+    }
+
+    pub fn is_permissioned(&self) -> bool {
+        self.permissioned
+    }
+
+    pub fn has_role(&self, role: Role, account_id: ValidAccountId) -> bool {
+        self.has_role_internal(role, account_id.as_ref())
+    }
+
+    /// Roles of `account_id`, the owner is not listed as an admin unless it was granted the role.
+    pub fn get_roles(&self, account_id: ValidAccountId) -> Vec<Role> {
+        Role::ALL
+            .iter()
+            .copied()
+            .filter(|role| self.has_role_internal(*role, account_id.as_ref()))
+            .collect()
+    }
+
+    /// Returns up to `limit` members of `role`, starting at index `from`. Revoking a role moves
+    /// its last member to the index of the revoked one.
+    pub fn get_role_members(&self, role: Role, from: u64, limit: u64) -> Vec<AccountId> {
+        let members = self.role_members(role);
+        let members = members.as_vector();
+        (from..std::cmp::min(from.saturating_add(limit), members.len()))
+            .map(|index| members.get(index).unwrap())
+            .collect()
+    }
+
+    pub fn get_role_member_count(&self, role: Role) -> u64 {
+        self.role_members(role).len()
+    }
+}
+
+impl ProofOfTimestamp {
+    fn role_members(&self, role: Role) -> UnorderedSet<AccountId> {
+        self.roles.get(&role).unwrap_or_else(|| {
+            let mut prefix = ROLE_MEMBERS_PREFIX.to_vec();
+            prefix.extend(role.try_to_vec().unwrap());
+            UnorderedSet::new(prefix)
+        })
+    }
+
+    fn has_role_internal(&self, role: Role, account_id: &AccountId) -> bool {
+        self.role_members(role).contains(account_id)
+    }
+
+    /// Only the owner manages admins, the owner and admins manage the other roles.
+    fn assert_role_manager(&self, role: Role) {
+        let account_id = env::predecessor_account_id();
+        let allowed = account_id == self.owner_id || (role != Role::Admin && self.has_role_internal(Role::Admin, &account_id));
+        if !allowed {
+            match role {
+                Role::Admin => env::panic(b"Only the owner can manage admins"),
+                _ => env::panic(b"Only the owner and admins can call this method"),
+            }
+        }
+    }
+
+    /// Panics unless `stamper` may stamp: stamping isn't paused, and `stamper` is a notary if
+    /// the contract is permissioned.
+    pub(crate) fn assert_can_stamp(&self, stamper: &AccountId) {
+        self.assert_not_paused();
+        self.assert_migrated();
+        if self.permissioned && !self.has_role_internal(Role::Notary, stamper) {
+            env::panic(format!("Only notaries can stamp, '{}' is not one", stamper).as_bytes());
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Hash-chained log of every stamp.
+//!
+//! Each stamp, indexed like in the `account_index` module, gets the next sequence number and
+//! a log entry whose hash commits to the hash of the previous entry:
+//!
+//! ```text
+//! head(seq) = keccak256(u32_be(len(DOMAIN)) || DOMAIN || head(seq - 1) || u64_be(seq) || u64_be(timestamp)
+//!                       || u64_be(block_height) || u32_be(len(file_hash)) || file_hash || u32_be(len(stamper)) || stamper)
+//! ```
+//!
+//! where `head(-1)` is 32 zero bytes. Given a trusted head, removing, altering or reordering
+//! any earlier entry changes it, which verifiers detect by recomputing the chain from a segment.
+//!
+//! The stamper learns the sequence number of its stamp from the `seq` of the stamp event, and
+//! finds it later in the stamp history and the account and time indexes.
+
+use crate::file_hash::encode_hex;
+use crate::ProofOfTimestamp;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, BlockHeight};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+pub const LOG_DOMAIN: &[u8] = b"near-proof-of-timestamp/log";
+/// Head of the empty log.
+pub const GENESIS_HEAD: [u8; 32] = [0; 32];
+/// Most entries `get_log_segment` returns.
+pub const MAX_LOG_SEGMENT_LEN: u64 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LogEntry {
+    pub(crate) file_hash: String,
+    pub(crate) stamper: AccountId,
+    pub(crate) timestamp: u64,
+    block_height: BlockHeight,
+    /// Hash of this entry, the head of the log once it was appended.
+    head: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LogEntryView {
+    pub seq: U64,
+    /// Canonical file hash, or `merkle-root:<root>` for a batch.
+    pub file_hash: String,
+    pub stamper: AccountId,
+    pub timestamp: U64,
+    pub block_height: U64,
+    /// Hex encoded head before this entry.
+    pub previous_head: String,
+    /// Hex encoded hash of this entry.
+    pub head: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LogHead {
+    /// Number of entries, the sequence number of the next stamp.
+    pub length: U64,
+    /// Hex encoded hash of the last entry, `GENESIS_HEAD` for the empty log.
+    pub head: String,
+}
+
+/// Bytes hashed into the head of the log entry `seq`, see the module documentation.
+pub fn log_entry_preimage(
+    previous_head: &[u8],
+    seq: u64,
+    timestamp: u64,
+    block_height: BlockHeight,
+    file_hash: &str,
+    stamper: &str,
+) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(4 + LOG_DOMAIN.len() + previous_head.len() + 24 + 4 + file_hash.len() + 4 + stamper.len());
+    preimage.extend(&(LOG_DOMAIN.len() as u32).to_be_bytes());
+    preimage.extend(LOG_DOMAIN);
+    preimage.extend(previous_head);
+    preimage.extend(&seq.to_be_bytes());
+    preimage.extend(&timestamp.to_be_bytes());
+    preimage.extend(&block_height.to_be_bytes());
+    preimage.extend(&(file_hash.len() as u32).to_be_bytes());
+    preimage.extend(file_hash.as_bytes());
+    preimage.extend(&(stamper.len() as u32).to_be_bytes());
+    preimage.extend(stamper.as_bytes());
+    preimage
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    pub fn get_log_head(&self) -> LogHead {
+        LogHead { length: self.log.len().into(), head: encode_hex(&self.log_head_before(self.log.len())) }
+    }
+
+    pub fn get_log_entry(&self, seq: U64) -> Option<LogEntryView> {
+        self.log.get(seq.0).map(|entry| self.log_entry_view(seq.0, entry))
+    }
+
+    /// Returns the entries from `from_seq` included to `to_seq` excluded, a range of at most
+    /// `MAX_LOG_SEGMENT_LEN` sequence numbers.
+    pub fn get_log_segment(&self, from_seq: U64, to_seq: U64) -> Vec<LogEntryView> {
+        assert!(
+            to_seq.0.saturating_sub(from_seq.0) <= MAX_LOG_SEGMENT_LEN,
+            "A segment has at most {} entries",
+            MAX_LOG_SEGMENT_LEN
+        );
+        (from_seq.0..std::cmp::min(to_seq.0, self.log.len())).map(|seq| self.log_entry_view(seq, self.log.get(seq).unwrap())).collect()
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Appends the stamp of `file_hash` by `stamper`, made in the current block, to the log and
+    /// returns its sequence number.
+    pub(crate) fn append_to_log(&mut self, stamper: &AccountId, file_hash: &str) -> u64 {
+        let seq = self.log.len();
+        let (timestamp, block_height) = (env::block_timestamp(), env::block_index());
+        let previous_head = self.log_head_before(seq);
+        let head = env::keccak256(&log_entry_preimage(&previous_head, seq, timestamp, block_height, file_hash, stamper));
+        self.log.push(&LogEntry { file_hash: file_hash.to_string(), stamper: stamper.clone(), timestamp, block_height, head });
+        seq
+    }
+
+    /// Entry `seq` of the log, which must exist.
+    pub(crate) fn log_entry(&self, seq: u64) -> LogEntry {
+        self.log.get(seq).unwrap()
+    }
+
+    fn log_head_before(&self, seq: u64) -> Vec<u8> {
+        match seq.checked_sub(1) {
+            Some(previous) => self.log.get(previous).unwrap().head,
+            None => GENESIS_HEAD.to_vec(),
+        }
+    }
+
+    fn log_entry_view(&self, seq: u64, entry: LogEntry) -> LogEntryView {
+        LogEntryView {
+            seq: seq.into(),
+            file_hash: entry.file_hash,
+            stamper: entry.stamper,
+            timestamp: entry.timestamp.into(),
+            block_height: entry.block_height.into(),
+            previous_head: encode_hex(&self.log_head_before(seq)),
+            head: encode_hex(&entry.head),
+        }
+    }
+}
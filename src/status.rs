@@ -0,0 +1,115 @@
+//! Revocation and supersession of stamps.
+//!
+//! The original stamper of a file hash can mark its stamp as revoked, with a reason, or as
+//! superseded by the stamp of another file hash, e.g. when a draft is withdrawn or replaced.
+//! Either is final. The record, its commitment and its history are kept unchanged, the status
+//! is stored next to them and shown by `get_stamp`.
+
+use crate::events::{Event, StampRevokedData, StampSupersededData};
+use crate::{ProofOfTimestamp, TimestampedFile};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+/// Longest revocation reason accepted, in bytes.
+pub const MAX_REASON_LEN: usize = 512;
+
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", tag = "state", rename_all = "snake_case")]
+pub enum StampStatus {
+    #[default]
+    Active,
+    Revoked {
+        reason: String,
+        /// Block timestamp of the revocation.
+        timestamp: U64,
+    },
+    Superseded {
+        /// Canonical file hash of the replacement.
+        superseded_by: String,
+        /// Block timestamp of the supersession.
+        timestamp: U64,
+    },
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Marks the stamp of `file_hash` as revoked. Only its original stamper can, and the
+    /// attached deposit pays for the storage of the status like for a stamp.
+    #[payable]
+    pub fn revoke_stamp(&mut self, file_hash: String, reason: String) {
+        let initial_storage_usage = env::storage_usage();
+        let file_hash = self.assert_status_change(&file_hash);
+        assert!(
+            reason.len() <= MAX_REASON_LEN,
+            "Reason is {} bytes long, at most {} are accepted",
+            reason.len(),
+            MAX_REASON_LEN
+        );
+        let timestamp = env::block_timestamp();
+        self.statuses.insert(&file_hash, &StampStatus::Revoked { reason: reason.clone(), timestamp: timestamp.into() });
+        Event::StampRevoked(vec![StampRevokedData {
+            file_hash,
+            reason,
+            timestamp: timestamp.into(),
+            stamper: env::predecessor_account_id(),
+        }])
+        .emit();
+        self.charge_storage(&env::predecessor_account_id(), env::attached_deposit(), initial_storage_usage);
+    }
+
+    /// Marks the stamp of `old_hash` as superseded by the stamp of `new_hash`, which must be
+    /// stamped already. Only the original stamper of `old_hash` can, and the attached deposit
+    /// pays for the storage of the status like for a stamp.
+    #[payable]
+    pub fn supersede_stamp(&mut self, old_hash: String, new_hash: String) {
+        let initial_storage_usage = env::storage_usage();
+        let old_hash = self.assert_status_change(&old_hash);
+        let new_hash = self.record_key(&new_hash);
+        assert!(self.records.contains_key(&new_hash), "Replacement '{}' must be stamped first", new_hash);
+        assert_ne!(old_hash, new_hash, "A stamp can't supersede itself");
+        let timestamp = env::block_timestamp();
+        self.statuses.insert(
+            &old_hash,
+            &StampStatus::Superseded { superseded_by: new_hash.clone(), timestamp: timestamp.into() },
+        );
+        Event::StampSuperseded(vec![StampSupersededData {
+            file_hash: old_hash,
+            superseded_by: new_hash,
+            timestamp: timestamp.into(),
+            stamper: env::predecessor_account_id(),
+        }])
+        .emit();
+        self.charge_storage(&env::predecessor_account_id(), env::attached_deposit(), initial_storage_usage);
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Status of the record stored under `file_hash`.
+    pub(crate) fn stamp_status(&self, file_hash: &String) -> StampStatus {
+        self.statuses.get(file_hash).unwrap_or_default()
+    }
+
+    /// Returns the record key of `file_hash` if the predecessor can change its status: the
+    /// contract isn't paused, the predecessor is the original stamper and the stamp is active.
+    fn assert_status_change(&self, file_hash: &str) -> String {
+        self.assert_not_paused();
+        let file_hash = self.record_key(file_hash);
+        let record: TimestampedFile = self
+            .records
+            .get(&file_hash)
+            .unwrap_or_else(|| env::panic(format!("'{}' is not stamped", file_hash).as_bytes()))
+            .into();
+        assert_eq!(
+            Some(env::predecessor_account_id()),
+            record.stamper,
+            "Only the original stamper can change the status of a stamp"
+        );
+        assert_eq!(StampStatus::Active, self.stamp_status(&file_hash), "Stamp of '{}' is no longer active", file_hash);
+        file_hash
+    }
+}
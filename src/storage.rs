@@ -0,0 +1,201 @@
+//! Storage staking: the account that stamps pays for the contract state its stamp adds, so
+//! that stamping can't drain the balance of the contract account.
+//!
+//! Accounts either attach a deposit to every stamp, the unused part being refunded, or prepay
+//! a storage balance through the NEP-145 methods below and have their stamps charged to it.
+//! Stamps are never deleted, so the bytes an account paid for stay locked in its balance.
+
+use crate::ProofOfTimestamp;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise, StorageUsage};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+/// Price of one byte of contract state, 1 NEAR per 100 kB.
+pub const STORAGE_PRICE_PER_BYTE: Balance = 10_000_000_000_000_000_000;
+
+/// Upper bound of the bytes taken by the entry of a registered account: the key prefix and
+/// a 64 characters account ID, its `StorageAccount` and the 40 bytes of overhead per record.
+pub const ACCOUNT_STORAGE_BYTES: StorageUsage = 1 + 4 + 64 + 24 + 40;
+
+/// Prepaid storage of a registered account.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StorageAccount {
+    balance: Balance,
+    /// Bytes of the stamps charged to the account, on top of `ACCOUNT_STORAGE_BYTES`.
+    used_bytes: StorageUsage,
+}
+
+impl StorageAccount {
+    fn locked(&self) -> Balance {
+        Balance::from(ACCOUNT_STORAGE_BYTES + self.used_bytes) * STORAGE_PRICE_PER_BYTE
+    }
+
+    fn to_balance(&self) -> StorageBalance {
+        StorageBalance { total: self.balance.into(), available: (self.balance - self.locked()).into() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Registers `account_id`, the predecessor by default, or tops up its storage balance.
+    /// With `registration_only`, only the minimum balance is kept and the rest is refunded.
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let account_id: AccountId = account_id.map(Into::into).unwrap_or_else(env::predecessor_account_id);
+        let amount = env::attached_deposit();
+        let registration_only = registration_only.unwrap_or(false);
+        let account = match self.storage_accounts.get(&account_id) {
+            Some(mut account) => {
+                if registration_only {
+                    refund(amount);
+                } else {
+                    account.balance += amount;
+                }
+                account
+            }
+            None => {
+                let min = storage_balance_min();
+                if amount < min {
+                    env::panic(format!("Must attach at least {} yoctoNEAR to register, attached {}", min, amount).as_bytes());
+                }
+                if registration_only {
+                    refund(amount - min);
+                }
+                StorageAccount { balance: if registration_only { min } else { amount }, used_bytes: 0 }
+            }
+        };
+        self.storage_accounts.insert(&account_id, &account);
+        account.to_balance()
+    }
+
+    /// Withdraws `amount`, all of the available balance by default, to the predecessor.
+    /// Requires a deposit of exactly 1 yoctoNEAR.
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account = self.storage_account(&account_id);
+        let available = account.balance - account.locked();
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        if amount > available {
+            env::panic(format!("Can't withdraw {} yoctoNEAR, {} are available", amount, available).as_bytes());
+        }
+        account.balance -= amount;
+        self.storage_accounts.insert(&account_id, &account);
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+        account.to_balance()
+    }
+
+    /// Unregisters the predecessor and refunds its balance, returns `false` if it wasn't
+    /// registered. Stamps can't be deleted: an account that has stamps can only unregister
+    /// with `force`, and the part of its balance that pays for them is not refunded.
+    /// Requires a deposit of exactly 1 yoctoNEAR.
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let account = match self.storage_accounts.get(&account_id) {
+            Some(account) => account,
+            None => return false,
+        };
+        if account.used_bytes > 0 && !force.unwrap_or(false) {
+            env::panic(b"Can't unregister an account that has stamps without force, the storage they use would not be refunded");
+        }
+        self.storage_accounts.remove(&account_id);
+        let refund = account.balance - Balance::from(account.used_bytes) * STORAGE_PRICE_PER_BYTE;
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+        true
+    }
+
+    pub fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(account_id.as_ref()).map(|account| account.to_balance())
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds { min: storage_balance_min().into(), max: None }
+    }
+}
+
+impl ProofOfTimestamp {
+    fn storage_account(&self, account_id: &AccountId) -> StorageAccount {
+        self.storage_accounts
+            .get(account_id)
+            .unwrap_or_else(|| env::panic(format!("Account '{}' is not registered", account_id).as_bytes()))
+    }
+
+    /// Charges the state added since `initial_storage_usage` to `account_id`. A registered
+    /// account is credited with `deposit` and then charged on its storage balance. Otherwise
+    /// `deposit` must cover the cost and what is left of it is refunded.
+    pub(crate) fn charge_storage(&mut self, account_id: &AccountId, deposit: Balance, initial_storage_usage: StorageUsage) {
+        let bytes = env::storage_usage().saturating_sub(initial_storage_usage);
+        let cost = Balance::from(bytes) * STORAGE_PRICE_PER_BYTE;
+        match self.storage_accounts.get(account_id) {
+            Some(mut account) => {
+                account.balance += deposit;
+                account.used_bytes += bytes;
+                if account.locked() > account.balance {
+                    env::panic(
+                        format!(
+                            "Storage balance of '{}' can't cover {} bytes of storage, deposit {} more yoctoNEAR with storage_deposit",
+                            account_id,
+                            bytes,
+                            account.locked() - account.balance
+                        )
+                        .as_bytes(),
+                    );
+                }
+                self.storage_accounts.insert(account_id, &account);
+            }
+            None => {
+                if cost > deposit {
+                    env::panic(
+                        format!(
+                            "Must attach {} yoctoNEAR to cover {} bytes of storage, attached {}",
+                            cost, bytes, deposit
+                        )
+                        .as_bytes(),
+                    );
+                }
+                if deposit > cost {
+                    Promise::new(account_id.clone()).transfer(deposit - cost);
+                }
+            }
+        }
+    }
+}
+
+pub fn storage_balance_min() -> Balance {
+    Balance::from(ACCOUNT_STORAGE_BYTES) * STORAGE_PRICE_PER_BYTE
+}
+
+fn refund(amount: Balance) {
+    if amount > 0 {
+        Promise::new(env::predecessor_account_id()).transfer(amount);
+    }
+}
+
+pub(crate) fn assert_one_yocto() {
+    assert_eq!(env::attached_deposit(), 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+}
@@ -0,0 +1,126 @@
+//! Mocked blockchain for the unit tests that also records the logs of the contract.
+//!
+//! The `MockedBlockchain` of near-sdk 2 keeps the logs to itself and this release has no
+//! `near_sdk::test_utils::get_logs`. The `testing_env!` of this module installs the mocked
+//! blockchain behind `LoggingBlockchain`, which records every log on its way, and `get_logs`
+//! returns the logs written since the last `testing_env!`, like later releases of near-sdk do.
+
+use near_sdk::serde_json::{self, Value};
+use near_sdk::{env, BlockchainInterface, MockedBlockchain, PromiseResult, VMContext};
+use std::cell::RefCell;
+
+/// Installs a mocked blockchain running in `context`, keeping the storage of the previous one.
+macro_rules! testing_env {
+    ($context:expr) => {
+        $crate::test_utils::testing_env_with_promise_results($context, vec![])
+    };
+}
+
+thread_local! {
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Same as `testing_env!`, for callbacks that read the results of `promise_results`.
+pub fn testing_env_with_promise_results(context: VMContext, promise_results: Vec<PromiseResult>) {
+    let storage = match env::take_blockchain_interface() {
+        Some(mut blockchain) => blockchain.as_mut_mocked_blockchain().unwrap().take_storage(),
+        None => Default::default(),
+    };
+    let mocked = MockedBlockchain::new(context, Default::default(), Default::default(), promise_results, storage, Default::default());
+    LOGS.with(|logs| logs.borrow_mut().clear());
+    env::set_blockchain_interface(Box::new(LoggingBlockchain(mocked)));
+}
+
+/// Logs written since the last `testing_env!`.
+pub fn get_logs() -> Vec<String> {
+    LOGS.with(|logs| logs.borrow().clone())
+}
+
+/// Events among `get_logs`, parsed back from their NEP-297 log.
+pub fn get_events() -> Vec<Value> {
+    get_logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect()
+}
+
+struct LoggingBlockchain(MockedBlockchain);
+
+macro_rules! delegate {
+    ($(fn $name:ident(&self $(, $arg:ident: u64)*) $(-> $ret:ty)?;)*) => {
+        $(
+            unsafe fn $name(&self $(, $arg: u64)*) $(-> $ret)? {
+                unsafe { self.0.$name($($arg),*) }
+            }
+        )*
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+impl BlockchainInterface for LoggingBlockchain {
+    unsafe fn log_utf8(&self, len: u64, ptr: u64) {
+        // The mocked memory is the memory of the test, pointers are plain addresses
+        let log = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+        LOGS.with(|logs| logs.borrow_mut().push(String::from_utf8_lossy(log).into_owned()));
+        unsafe { self.0.log_utf8(len, ptr) }
+    }
+
+    delegate! {
+        fn read_register(&self, register_id: u64, ptr: u64);
+        fn register_len(&self, register_id: u64) -> u64;
+        fn current_account_id(&self, register_id: u64);
+        fn signer_account_id(&self, register_id: u64);
+        fn signer_account_pk(&self, register_id: u64);
+        fn predecessor_account_id(&self, register_id: u64);
+        fn input(&self, register_id: u64);
+        fn block_index(&self) -> u64;
+        fn block_timestamp(&self) -> u64;
+        fn epoch_height(&self) -> u64;
+        fn storage_usage(&self) -> u64;
+        fn account_balance(&self, balance_ptr: u64);
+        fn account_locked_balance(&self, balance_ptr: u64);
+        fn attached_deposit(&self, balance_ptr: u64);
+        fn prepaid_gas(&self) -> u64;
+        fn used_gas(&self) -> u64;
+        fn random_seed(&self, register_id: u64);
+        fn sha256(&self, value_len: u64, value_ptr: u64, register_id: u64);
+        fn keccak256(&self, value_len: u64, value_ptr: u64, register_id: u64);
+        fn keccak512(&self, value_len: u64, value_ptr: u64, register_id: u64);
+        fn value_return(&self, value_len: u64, value_ptr: u64);
+        fn panic(&self);
+        fn panic_utf8(&self, len: u64, ptr: u64);
+        fn log_utf16(&self, len: u64, ptr: u64);
+        fn promise_create(&self, account_id_len: u64, account_id_ptr: u64, method_name_len: u64, method_name_ptr: u64, arguments_len: u64, arguments_ptr: u64, amount_ptr: u64, gas: u64) -> u64;
+        fn promise_then(&self, promise_index: u64, account_id_len: u64, account_id_ptr: u64, method_name_len: u64, method_name_ptr: u64, arguments_len: u64, arguments_ptr: u64, amount_ptr: u64, gas: u64) -> u64;
+        fn promise_and(&self, promise_idx_ptr: u64, promise_idx_count: u64) -> u64;
+        fn promise_batch_create(&self, account_id_len: u64, account_id_ptr: u64) -> u64;
+        fn promise_batch_then(&self, promise_index: u64, account_id_len: u64, account_id_ptr: u64) -> u64;
+        fn promise_batch_action_create_account(&self, promise_index: u64);
+        fn promise_batch_action_deploy_contract(&self, promise_index: u64, code_len: u64, code_ptr: u64);
+        fn promise_batch_action_function_call(&self, promise_index: u64, method_name_len: u64, method_name_ptr: u64, arguments_len: u64, arguments_ptr: u64, amount_ptr: u64, gas: u64);
+        fn promise_batch_action_transfer(&self, promise_index: u64, amount_ptr: u64);
+        fn promise_batch_action_stake(&self, promise_index: u64, amount_ptr: u64, public_key_len: u64, public_key_ptr: u64);
+        fn promise_batch_action_add_key_with_full_access(&self, promise_index: u64, public_key_len: u64, public_key_ptr: u64, nonce: u64);
+        fn promise_batch_action_add_key_with_function_call(&self, promise_index: u64, public_key_len: u64, public_key_ptr: u64, nonce: u64, allowance_ptr: u64, receiver_id_len: u64, receiver_id_ptr: u64, method_names_len: u64, method_names_ptr: u64);
+        fn promise_batch_action_delete_key(&self, promise_index: u64, public_key_len: u64, public_key_ptr: u64);
+        fn promise_batch_action_delete_account(&self, promise_index: u64, beneficiary_id_len: u64, beneficiary_id_ptr: u64);
+        fn promise_results_count(&self) -> u64;
+        fn promise_result(&self, result_idx: u64, register_id: u64) -> u64;
+        fn promise_return(&self, promise_id: u64);
+        fn storage_write(&self, key_len: u64, key_ptr: u64, value_len: u64, value_ptr: u64, register_id: u64) -> u64;
+        fn storage_read(&self, key_len: u64, key_ptr: u64, register_id: u64) -> u64;
+        fn storage_remove(&self, key_len: u64, key_ptr: u64, register_id: u64) -> u64;
+        fn storage_has_key(&self, key_len: u64, key_ptr: u64) -> u64;
+        fn validator_stake(&self, account_id_len: u64, account_id_ptr: u64, stake_ptr: u64);
+        fn validator_total_stake(&self, stake_ptr: u64);
+    }
+
+    fn as_mut_mocked_blockchain(&mut self) -> Option<&mut MockedBlockchain> {
+        Some(&mut self.0)
+    }
+
+    fn as_mocked_blockchain(&self) -> Option<&MockedBlockchain> {
+        Some(&self.0)
+    }
+}
@@ -0,0 +1,144 @@
+//! Stamps ordered by time, to list every stamp made between two instants.
+//!
+//! Stamps are indexed like in the `account_index` module, every stamp of a file hash and every
+//! batch root once, under the key (block timestamp, sequence number). The sequence number is the
+//! position of the stamp in the `stamp_log` module, so stamps of the same block keep the order
+//! they were made in, and the index only holds the keys: stamps are read from the log. Ranges
+//! include `from_ns` and exclude `to_ns`.
+//!
+//! Pages hold at most `MAX_PAGE_SIZE` stamps and end with an opaque cursor to pass back to get
+//! the next page, `null` on the last page. A cursor never moves the start before `from_ns`.
+//! The stamps of an account are read from its index in the `account_index` module, which is
+//! already in time order.
+
+use crate::ProofOfTimestamp;
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use std::ops::Bound;
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+/// Most stamps a page holds, whatever the `limit` asked for.
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+/// Key of a stamp in the time index: its block timestamp and its sequence number.
+pub type TimeKey = (u64, u64);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimedStamp {
+    /// Canonical file hash, or `merkle-root:<root>` for a batch.
+    pub file_hash: String,
+    pub stamper: AccountId,
+    pub timestamp: U64,
+    /// Sequence number of the stamp in the stamp log.
+    pub seq: U64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StampPage {
+    pub stamps: Vec<TimedStamp>,
+    pub next_cursor: Option<String>,
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Returns up to `limit` stamps made from `from_ns` included to `to_ns` excluded, oldest
+    /// first, starting after `cursor` if set. `limit` is capped at `MAX_PAGE_SIZE`.
+    pub fn get_stamps_between(&self, from_ns: U64, to_ns: U64, limit: u64, cursor: Option<String>) -> StampPage {
+        let limit = std::cmp::min(limit, MAX_PAGE_SIZE);
+        let from = (from_ns.0, 0);
+        let start = match cursor.map(|cursor| parse_time_cursor(&cursor)) {
+            Some(cursor) if cursor >= from => Bound::Excluded(cursor),
+            _ => Bound::Included(from),
+        };
+        let end = (to_ns.0, 0);
+        if limit == 0 || matches!(start, Bound::Excluded(key) | Bound::Included(key) if key >= end) {
+            return StampPage { stamps: vec![], next_cursor: None };
+        }
+        let mut entries: Vec<_> = self.time_index.range((start, Bound::Excluded(end))).take(limit as usize + 1).collect();
+        let next_cursor = if entries.len() as u64 > limit {
+            entries.pop();
+            entries.last().map(|((timestamp, seq), ())| format!("{}:{}", timestamp, seq))
+        } else {
+            None
+        };
+        let stamps = entries
+            .into_iter()
+            .map(|((_, seq), ())| self.timed_stamp(seq))
+            .collect();
+        StampPage { stamps, next_cursor }
+    }
+
+    /// Same as `get_stamps_between`, only listing the stamps of `account_id`.
+    pub fn get_account_stamps_between(
+        &self,
+        account_id: ValidAccountId,
+        from_ns: U64,
+        to_ns: U64,
+        limit: u64,
+        cursor: Option<String>,
+    ) -> StampPage {
+        let empty = StampPage { stamps: vec![], next_cursor: None };
+        let stamps = match self.account_stamps.get(account_id.as_ref()) {
+            Some(stamps) => stamps,
+            None => return empty,
+        };
+        let limit = std::cmp::min(limit, MAX_PAGE_SIZE);
+        let timestamp = |index| self.log_entry(stamps.get(index).unwrap()).timestamp;
+        let mut start = partition_point(stamps.len(), |index| timestamp(index) < from_ns.0);
+        if let Some(cursor) = cursor {
+            let after_cursor = cursor.parse::<u64>().unwrap_or_else(|_| env::panic(b"Invalid cursor")).saturating_add(1);
+            start = std::cmp::max(start, after_cursor);
+        }
+        let end = partition_point(stamps.len(), |index| timestamp(index) < to_ns.0);
+        let page_end = std::cmp::min(start.saturating_add(limit), end);
+        if start >= page_end {
+            return empty;
+        }
+        StampPage {
+            stamps: (start..page_end)
+                .map(|index| self.timed_stamp(stamps.get(index).unwrap()))
+                .collect(),
+            next_cursor: if page_end < end { Some((page_end - 1).to_string()) } else { None },
+        }
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Adds the stamp `seq`, made in the current block, to the time index.
+    pub(crate) fn index_time_stamp(&mut self, seq: u64) {
+        self.time_index.insert(&(env::block_timestamp(), seq), &());
+    }
+
+    fn timed_stamp(&self, seq: u64) -> TimedStamp {
+        let entry = self.log_entry(seq);
+        TimedStamp { file_hash: entry.file_hash, stamper: entry.stamper, timestamp: entry.timestamp.into(), seq: seq.into() }
+    }
+}
+
+fn parse_time_cursor(cursor: &str) -> TimeKey {
+    let mut parts = cursor.splitn(2, ':').map(str::parse::<u64>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(timestamp)), Some(Ok(seq))) => (timestamp, seq),
+        _ => env::panic(b"Invalid cursor"),
+    }
+}
+
+/// Index of the first of the `len` elements for which `is_before` is false, `is_before` being
+/// true for a prefix of the elements.
+fn partition_point(len: u64, is_before: impl Fn(u64) -> bool) -> u64 {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if is_before(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
@@ -0,0 +1,189 @@
+//! Append-only Merkle tree over the stamp log, in the manner of Certificate Transparency.
+//!
+//! The entry `seq` of the `stamp_log` module is the leaf `seq` of a tree hashed like the trees
+//! of the `merkle` module, i.e. the tree of RFC 6962, section 2.1, with keccak256:
+//!
+//! ```text
+//! leaf = keccak256(0x00 || u32_be(len(DOMAIN)) || DOMAIN || u64_be(seq) || u64_be(timestamp) || u64_be(block_height)
+//!                  || u32_be(len(file_hash)) || file_hash || u32_be(len(stamper)) || stamper)
+//! ```
+//!
+//! `get_tree_head` publishes the size and root of the tree. A verifier that kept an earlier
+//! head checks with `get_consistency_proof` that the current tree only appends to it, and
+//! checks with `get_inclusion_proof` that an entry is in a tree it holds the head of. Proofs
+//! follow sections 2.1.1 and 2.1.2 of RFC 6962.
+//!
+//! The root of every perfect subtree is stored once all its leaves are appended, so appending a
+//! leaf stores two nodes on average and any root or proof is built from `O(log n)` stored nodes.
+
+use crate::file_hash::encode_hex;
+use crate::merkle::{self, MerkleProof, Side};
+use crate::ProofOfTimestamp;
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, BlockHeight};
+
+#[cfg(target_arch = "wasm32")]
+use crate::near_blockchain;
+
+pub const LEAF_DOMAIN: &[u8] = b"near-proof-of-timestamp/leaf";
+
+/// Position of a stored node: its height above the leaves and its index among the nodes of
+/// that height. The node `(height, index)` is the root of the leaves from `index << height`
+/// included to `(index + 1) << height` excluded.
+pub type NodePosition = (u8, u64);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreeHead {
+    /// Number of leaves, the length of the stamp log.
+    pub size: U64,
+    /// Hex encoded root, `keccak256("")` for the empty tree.
+    pub root: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InclusionProof {
+    pub leaf_index: U64,
+    pub tree_size: U64,
+    /// Hex encoded leaf hash of the entry.
+    pub leaf_hash: String,
+    /// Recomputes the root of the tree of `tree_size` leaves from `leaf_hash`.
+    pub proof: MerkleProof,
+}
+
+/// Data hashed into the leaf of the log entry `seq`, see the module documentation.
+pub fn log_leaf_data(seq: u64, timestamp: u64, block_height: BlockHeight, file_hash: &str, stamper: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + LEAF_DOMAIN.len() + 24 + 4 + file_hash.len() + 4 + stamper.len());
+    data.extend(&(LEAF_DOMAIN.len() as u32).to_be_bytes());
+    data.extend(LEAF_DOMAIN);
+    data.extend(&seq.to_be_bytes());
+    data.extend(&timestamp.to_be_bytes());
+    data.extend(&block_height.to_be_bytes());
+    data.extend(&(file_hash.len() as u32).to_be_bytes());
+    data.extend(file_hash.as_bytes());
+    data.extend(&(stamper.len() as u32).to_be_bytes());
+    data.extend(stamper.as_bytes());
+    data
+}
+
+#[near_bindgen]
+impl ProofOfTimestamp {
+    /// Current size and root of the tree.
+    pub fn get_tree_head(&self) -> TreeHead {
+        let size = self.log.len();
+        let root = if size == 0 { env::keccak256(b"") } else { self.subtree_root(0, size) };
+        TreeHead { size: size.into(), root: encode_hex(&root) }
+    }
+
+    /// Proof that the entry `leaf_index` is in the tree of `tree_size` leaves, the current
+    /// tree if not set.
+    pub fn get_inclusion_proof(&self, leaf_index: U64, tree_size: Option<U64>) -> InclusionProof {
+        let tree_size = self.assert_tree_size(tree_size);
+        assert!(leaf_index.0 < tree_size, "{}", merkle::MerkleError::LeafOutOfRange { index: leaf_index.0 as usize, leaf_count: tree_size as usize });
+        let mut proof = MerkleProof { siblings: vec![], sides: vec![] };
+        self.inclusion_path(leaf_index.0, 0, tree_size, &mut proof);
+        InclusionProof { leaf_index, tree_size: tree_size.into(), leaf_hash: encode_hex(&self.node((0, leaf_index.0))), proof }
+    }
+
+    /// Hex encoded hashes proving that the tree of `first_size` leaves is a prefix of the tree
+    /// of `second_size` leaves, the current tree if not set. Verified by
+    /// `merkle::verify_consistency`.
+    pub fn get_consistency_proof(&self, first_size: U64, second_size: Option<U64>) -> Vec<String> {
+        let second_size = self.assert_tree_size(second_size);
+        assert!(
+            0 < first_size.0 && first_size.0 <= second_size,
+            "First tree size must be between 1 and {}, got {}",
+            second_size,
+            first_size.0
+        );
+        let mut proof = vec![];
+        self.consistency_path(first_size.0, 0, second_size, true, &mut proof);
+        proof.iter().map(|hash| encode_hex(hash)).collect()
+    }
+}
+
+impl ProofOfTimestamp {
+    /// Appends the leaf of the log entry `seq`, made by `stamper` in the current block, and
+    /// stores the roots of the perfect subtrees it completes.
+    pub(crate) fn append_to_tree(&mut self, seq: u64, stamper: &AccountId, file_hash: &str) {
+        let data = log_leaf_data(seq, env::block_timestamp(), env::block_index(), file_hash, stamper);
+        let mut node = merkle::leaf_hash(&data);
+        let (mut height, mut index) = (0, seq);
+        self.tree_nodes.insert(&(height, index), &node);
+        while index % 2 == 1 {
+            node = merkle::node_hash(&self.node((height, index - 1)), &node);
+            height += 1;
+            index /= 2;
+            self.tree_nodes.insert(&(height, index), &node);
+        }
+    }
+
+    fn assert_tree_size(&self, tree_size: Option<U64>) -> u64 {
+        let size = self.log.len();
+        match tree_size {
+            Some(tree_size) => {
+                assert!(tree_size.0 <= size, "Tree size {} is larger than the current tree of {} leaves", tree_size.0, size);
+                tree_size.0
+            }
+            None => size,
+        }
+    }
+
+    fn node(&self, position: NodePosition) -> Vec<u8> {
+        self.tree_nodes.get(&position).unwrap()
+    }
+
+    /// Root of the `size` leaves from `start`, which must be a multiple of the largest power
+    /// of two not above `size`, as it is in every subtree of RFC 6962.
+    fn subtree_root(&self, start: u64, size: u64) -> Vec<u8> {
+        if size.is_power_of_two() {
+            let height = size.trailing_zeros();
+            return self.node((height as u8, start >> height));
+        }
+        let split = split_point(size);
+        merkle::node_hash(&self.subtree_root(start, split), &self.subtree_root(start + split, size - split))
+    }
+
+    /// PATH of RFC 6962, section 2.1.1, for the leaf `index` of the `size` leaves from `start`.
+    fn inclusion_path(&self, index: u64, start: u64, size: u64, proof: &mut MerkleProof) {
+        if size == 1 {
+            return;
+        }
+        let split = split_point(size);
+        let (sibling, side) = if index < split {
+            self.inclusion_path(index, start, split, proof);
+            (self.subtree_root(start + split, size - split), Side::Right)
+        } else {
+            self.inclusion_path(index - split, start + split, size - split, proof);
+            (self.subtree_root(start, split), Side::Left)
+        };
+        proof.siblings.push(encode_hex(&sibling));
+        proof.sides.push(side);
+    }
+
+    /// SUBPROOF of RFC 6962, section 2.1.2, of the first `first_size` of the `size` leaves from
+    /// `start`.
+    fn consistency_path(&self, first_size: u64, start: u64, size: u64, complete: bool, proof: &mut Vec<Vec<u8>>) {
+        if first_size == size {
+            if !complete {
+                proof.push(self.subtree_root(start, size));
+            }
+            return;
+        }
+        let split = split_point(size);
+        if first_size <= split {
+            self.consistency_path(first_size, start, split, complete, proof);
+            proof.push(self.subtree_root(start + split, size - split));
+        } else {
+            self.consistency_path(first_size - split, start + split, size - split, false, proof);
+            proof.push(self.subtree_root(start, split));
+        }
+    }
+}
+
+/// Largest power of two below `size`, which must be at least 2.
+fn split_point(size: u64) -> u64 {
+    1 << (63 - (size - 1).leading_zeros())
+}
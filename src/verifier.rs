@@ -0,0 +1,199 @@
+//! Offline verification of what the contract returns, without a NEAR environment.
+//!
+//! Every check recomputes hashes with the Rust keccak256 of `merkle::keccak256` and compares
+//! them with values the caller trusts: a record read from the chain, or a tree head or log head
+//! kept earlier. Views are the JSON views of the contract, deserialized with `serde_json`.
+//!
+//! * `verify_commitment` recomputes the commitment of a record (see the `commitment` module)
+//! * `verify_batch_inclusion` checks that a file hash is under a stamped batch root
+//! * `verify_log_inclusion` checks that a stamp log entry is a leaf of a tree head
+//! * `verify_log_consistency` checks that a tree head only appends to an earlier one
+//! * `verify_log_segment` recomputes the hash chain of stamp log entries up to a log head
+//!
+//! Only available outside of wasm, where the contract hashes through the host.
+
+use crate::commitment::{commitment_preimage, LEGACY_COMMITMENT_VERSION};
+use crate::file_hash::{decode_hex, encode_hex, FileHash, FileHashError};
+use crate::merkle::{self, keccak256, MerkleError, MerkleProof, Side};
+use crate::stamp_log::{log_entry_preimage, LogEntryView};
+use crate::transparency_log::{log_leaf_data, InclusionProof, TreeHead};
+use crate::views::{BatchRootView, TimestampedFileView};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    UnknownCommitmentVersion(u8),
+    CommitmentMismatch { file_hash: String },
+    InvalidFileHash(FileHashError),
+    Merkle(MerkleError),
+    /// A hash that should be hex encoded, named after its field.
+    InvalidHash(&'static str),
+    TreeSizeMismatch { proof: u64, head: u64 },
+    LeafMismatch { leaf_index: u64 },
+    /// The proof doesn't lead to the trusted root.
+    RootMismatch,
+    /// Entry `seq` doesn't follow the entry before it.
+    BrokenChain { seq: u64 },
+    /// The segment doesn't end at the trusted log head.
+    HeadMismatch,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::UnknownCommitmentVersion(version) => write!(f, "Unknown commitment version {}", version),
+            VerifyError::CommitmentMismatch { file_hash } => {
+                write!(f, "Commitment of '{}' doesn't match its file hash and timestamp", file_hash)
+            }
+            VerifyError::InvalidFileHash(err) => err.fmt(f),
+            VerifyError::Merkle(err) => err.fmt(f),
+            VerifyError::InvalidHash(field) => write!(f, "'{}' is not a hex encoded hash", field),
+            VerifyError::TreeSizeMismatch { proof, head } => {
+                write!(f, "Proof is for a tree of {} leaves, the tree head has {}", proof, head)
+            }
+            VerifyError::LeafMismatch { leaf_index } => write!(f, "Proof is not for the leaf {}", leaf_index),
+            VerifyError::RootMismatch => write!(f, "Proof doesn't lead to the trusted root"),
+            VerifyError::BrokenChain { seq } => write!(f, "Log entry {} doesn't follow the entry before it", seq),
+            VerifyError::HeadMismatch => write!(f, "Log segment doesn't end at the trusted head"),
+        }
+    }
+}
+
+impl From<MerkleError> for VerifyError {
+    fn from(err: MerkleError) -> Self {
+        VerifyError::Merkle(err)
+    }
+}
+
+/// Checks that the commitment of `stamp` is the commitment of its file hash and timestamp. The
+/// file hash of a migrated record is the one it was stamped under in the legacy state.
+pub fn verify_commitment(stamp: &TimestampedFileView) -> Result<(), VerifyError> {
+    let file_hash = match (stamp.commitment_version, &stamp.legacy_file_hash) {
+        (LEGACY_COMMITMENT_VERSION, Some(legacy_file_hash)) => legacy_file_hash,
+        _ => &stamp.file_hash,
+    };
+    let preimage = commitment_preimage(stamp.commitment_version, file_hash, stamp.timestamp.0)
+        .ok_or(VerifyError::UnknownCommitmentVersion(stamp.commitment_version))?;
+    if encode_hex(&keccak256(&preimage)) == stamp.commitment {
+        Ok(())
+    } else {
+        Err(VerifyError::CommitmentMismatch { file_hash: stamp.file_hash.clone() })
+    }
+}
+
+/// Checks that `proof` is the inclusion proof of `file_hash` under the root of `batch_root`,
+/// as returned by `get_batch_root`. The commitment of the root is checked separately, with
+/// `verify_commitment` on `batch_root.stamp`.
+pub fn verify_batch_inclusion(file_hash: &str, proof: &MerkleProof, batch_root: &BatchRootView) -> Result<(), VerifyError> {
+    let file_hash = FileHash::parse(file_hash).map_err(VerifyError::InvalidFileHash)?;
+    let root = proof.compute_root(&merkle::leaf_hash(file_hash.canonical().as_bytes()))?;
+    if encode_hex(&root) == batch_root.root {
+        Ok(())
+    } else {
+        Err(VerifyError::RootMismatch)
+    }
+}
+
+/// Checks that `proof`, as returned by `get_inclusion_proof`, proves that `entry` is a leaf of
+/// the tree of `head`. Unlike a batch proof, the shape of the proof must match the index of the
+/// leaf and the size of the tree.
+pub fn verify_log_inclusion(entry: &LogEntryView, proof: &InclusionProof, head: &TreeHead) -> Result<(), VerifyError> {
+    if proof.tree_size != head.size {
+        return Err(VerifyError::TreeSizeMismatch { proof: proof.tree_size.0, head: head.size.0 });
+    }
+    let data = log_leaf_data(entry.seq.0, entry.timestamp.0, entry.block_height.0, &entry.file_hash, &entry.stamper);
+    let leaf = merkle::leaf_hash(&data);
+    if proof.leaf_index != entry.seq || encode_hex(&leaf) != proof.leaf_hash {
+        return Err(VerifyError::LeafMismatch { leaf_index: entry.seq.0 });
+    }
+    if inclusion_sides(proof.leaf_index.0, proof.tree_size.0) != Some(proof.proof.sides.clone()) {
+        return Err(VerifyError::LeafMismatch { leaf_index: entry.seq.0 });
+    }
+    if encode_hex(&proof.proof.compute_root(&leaf)?) == head.root {
+        Ok(())
+    } else {
+        Err(VerifyError::RootMismatch)
+    }
+}
+
+/// Checks that `proof`, as returned by `get_consistency_proof`, proves that the tree of `first`
+/// is a prefix of the tree of `second`.
+pub fn verify_log_consistency(first: &TreeHead, second: &TreeHead, proof: &[String]) -> Result<(), VerifyError> {
+    let first_root = decode_hash(&first.root, "first.root")?;
+    let second_root = decode_hash(&second.root, "second.root")?;
+    Ok(merkle::verify_consistency(first.size.0, second.size.0, &first_root, &second_root, proof)?)
+}
+
+/// Recomputes the hash chain of `entries`, consecutive entries of the stamp log as returned by
+/// `get_log_segment`, from the hex encoded `previous_head` before the first of them, and checks
+/// that it ends at the hex encoded `head`, e.g. the head of `get_log_head` for a segment that
+/// ends the log.
+pub fn verify_log_segment(previous_head: &str, entries: &[LogEntryView], head: &str) -> Result<(), VerifyError> {
+    let mut chain_head = decode_hash(previous_head, "previous_head")?;
+    for (index, entry) in entries.iter().enumerate() {
+        let seq = entry.seq.0;
+        let follows = index == 0 || entries[index - 1].seq.0.checked_add(1) == Some(seq);
+        if !follows || entry.previous_head != encode_hex(&chain_head) {
+            return Err(VerifyError::BrokenChain { seq });
+        }
+        let preimage = log_entry_preimage(&chain_head, seq, entry.timestamp.0, entry.block_height.0, &entry.file_hash, &entry.stamper);
+        chain_head = keccak256(&preimage);
+        if entry.head != encode_hex(&chain_head) {
+            return Err(VerifyError::BrokenChain { seq });
+        }
+    }
+    if chain_head == decode_hash(head, "head")? {
+        Ok(())
+    } else {
+        Err(VerifyError::HeadMismatch)
+    }
+}
+
+fn decode_hash(value: &str, field: &'static str) -> Result<Vec<u8>, VerifyError> {
+    decode_hex(value).ok().filter(|hash| hash.len() == 32).ok_or(VerifyError::InvalidHash(field))
+}
+
+/// Sides of the siblings of an inclusion proof of the leaf `leaf_index` in a tree of
+/// `tree_size` leaves, the leaf's sibling first, `None` if the leaf isn't in the tree. Follows
+/// the verification of RFC 9162, section 2.1.3.2.
+fn inclusion_sides(leaf_index: u64, tree_size: u64) -> Option<Vec<Side>> {
+    if leaf_index >= tree_size {
+        return None;
+    }
+    let (mut index, mut last_index) = (leaf_index, tree_size - 1);
+    let mut sides = vec![];
+    while last_index > 0 {
+        if index % 2 == 1 {
+            sides.push(Side::Left);
+        } else if index < last_index {
+            sides.push(Side::Right);
+        }
+        // The last node of an odd level has no sibling and moves up as is
+        index /= 2;
+        last_index /= 2;
+    }
+    Some(sides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inclusion_sides_match_merkle_proofs() {
+        for count in 1..=17 {
+            let leaves: Vec<_> = (0..count).map(|i| merkle::leaf_hash(format!("leaf {}", i).as_bytes())).collect();
+            for index in 0..count {
+                let proof = merkle::merkle_proof(&leaves, index).unwrap();
+                assert_eq!(Some(proof.sides), inclusion_sides(index as u64, count as u64), "leaf {} of {}", index, count);
+            }
+            assert_eq!(None, inclusion_sides(count as u64, count as u64));
+        }
+    }
+
+    #[test]
+    fn keccak256_matches_the_host() {
+        // keccak256("") as returned by `env::keccak256`
+        assert_eq!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470", encode_hex(&keccak256(b"")));
+    }
+}
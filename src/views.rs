@@ -0,0 +1,107 @@
+//! JSON views returned to RPC callers. Binary fields are rendered as hex and 64-bit integers
+//! as strings, which JavaScript clients can't otherwise represent exactly.
+
+use crate::file_hash::encode_hex;
+use crate::status::StampStatus;
+use crate::TimestampedFile;
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::AccountId;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimestampedFileView {
+    /// Canonical form of the stamped file hash.
+    pub file_hash: String,
+    /// Block timestamp in nanoseconds since the Unix epoch.
+    pub timestamp: U64,
+    /// `timestamp` as an RFC 3339 UTC date, e.g. `2021-02-03T09:41:18.901234567Z`.
+    pub timestamp_rfc3339: String,
+    /// Hex encoded commitment.
+    pub commitment: String,
+    pub commitment_version: u8,
+    pub stamper: Option<AccountId>,
+    pub signer: Option<AccountId>,
+    pub block_height: Option<U64>,
+    pub epoch_height: Option<U64>,
+    /// Whether the stamp was revoked or superseded, see the `status` module.
+    pub status: StampStatus,
+    /// File hash as stamped in the legacy state, for migrated records whose hash was
+    /// canonicalized. The version 0 commitment covers it rather than `file_hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_file_hash: Option<String>,
+}
+
+/// A stamped Merkle root, see `ProofOfTimestamp::get_batched_stamp`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchRootView {
+    /// Hex encoded root.
+    pub root: String,
+    pub leaf_count: U64,
+    /// Record of the root, its `file_hash` is `merkle-root:<root>`.
+    pub stamp: TimestampedFileView,
+}
+
+impl TimestampedFileView {
+    pub fn new(file_hash: String, record: TimestampedFile, status: StampStatus) -> Self {
+        Self {
+            file_hash,
+            timestamp: record.timestamp.into(),
+            timestamp_rfc3339: format_rfc3339(record.timestamp),
+            commitment: encode_hex(&record.time_stamped_file_hash),
+            commitment_version: record.commitment_version,
+            stamper: record.stamper,
+            signer: record.signer,
+            block_height: record.block_height.map(U64),
+            epoch_height: record.epoch_height.map(U64),
+            status,
+            legacy_file_hash: None,
+        }
+    }
+}
+
+/// Formats nanoseconds since the Unix epoch as an RFC 3339 UTC date with nanosecond precision.
+pub fn format_rfc3339(timestamp_ns: u64) -> String {
+    let seconds = timestamp_ns / 1_000_000_000;
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        timestamp_ns % 1_000_000_000
+    )
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian (year, month, day), see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_rfc3339() {
+        assert_eq!("1970-01-01T00:00:00.000000000Z", format_rfc3339(0));
+        assert_eq!("2021-02-03T09:41:18.901234567Z", format_rfc3339(1_612_345_278_901_234_567));
+        assert_eq!("2000-02-29T23:59:59.000000001Z", format_rfc3339(951_868_799_000_000_001));
+        assert_eq!("2554-07-21T23:34:33.709551615Z", format_rfc3339(u64::MAX));
+    }
+}